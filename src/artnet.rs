@@ -0,0 +1,100 @@
+// https://art-net.org.uk/resources/art-net-specification/
+
+use std::collections::HashMap;
+use std::net::UdpSocket;
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+use tracing::warn;
+use crate::OscHandler;
+
+/// Addresses of the received DMX channels, routed by `[[route]]`
+pub const PREFIX: &str = "/artnet/";
+
+const HEADER: &[u8] = b"Art-Net\0";
+const OP_DMX: u16 = 0x5000;
+const DATA_OFFSET: usize = 18;
+
+/// Receives ArtDmx packets as OSC messages `/artnet/<universe>/<channel> <value>` whenever the channel value changes;
+/// channels are 1..512, values 0..255
+pub fn artnet_listen(host_port: &str, osc_handler: OscHandler) {
+    let socket = UdpSocket::bind(host_port).expect("Invalid --artnet-listen: couldn't bind socket");
+    let mut universes: HashMap<u16, Vec<u8>> = HashMap::new();
+    let mut buffer = [0u8; 1024];
+    loop {
+        let size = match socket.recv_from(&mut buffer) {
+            Ok((size, _)) => size,
+            Err(e) => {
+                warn!("[Art-Net] {:?}", e);
+                continue;
+            }
+        };
+        let (universe, data) = match dmx(&buffer[..size]) {
+            Some(dmx) => dmx,
+            None => continue,
+        };
+        let last = universes.entry(universe).or_default();
+        last.resize(last.len().max(data.len()), 0);
+        for (i, value) in data.iter().enumerate() {
+            if last[i] == *value {
+                continue;
+            }
+            last[i] = *value;
+            osc_handler.handle_packet(osc::Packet::Message(osc::Message {
+                addr: format!("{}{}/{}", PREFIX, universe, i + 1),
+                args: Some(vec![OscType::Int((*value).into())]),
+            }));
+        }
+    }
+}
+
+/// Universe (15-bit port-address) and channel values of the ArtDmx packet
+fn dmx(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if !packet.starts_with(HEADER) || packet.len() < DATA_OFFSET {
+        return None;
+    }
+    if u16::from_le_bytes([packet[8], packet[9]]) != OP_DMX {
+        return None;
+    }
+    let universe = u16::from_le_bytes([packet[14], packet[15] & 0x7F]);
+    let length = usize::from(u16::from_be_bytes([packet[16], packet[17]]));
+    packet.get(DATA_OFFSET..DATA_OFFSET + length).map(|data| (universe, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(opcode: u16, sub_uni: u8, net: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = HEADER.to_vec();
+        packet.extend_from_slice(&opcode.to_le_bytes());
+        // protocol version 14, sequence, physical port
+        packet.extend_from_slice(&[0, 14, 0, 0]);
+        packet.extend_from_slice(&[sub_uni, net]);
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn dmx_universe_and_channels() {
+        assert_eq!(dmx(&packet(OP_DMX, 0x12, 0x03, &[0, 128, 255])), Some((0x0312, &[0, 128, 255][..])));
+        // the top bit of net is not part of the port-address
+        assert_eq!(dmx(&packet(OP_DMX, 0x01, 0x80, &[7])), Some((0x0001, &[7][..])));
+    }
+
+    #[test]
+    fn other_packets_are_ignored() {
+        assert_eq!(dmx(&packet(0x2000, 0, 0, &[1])), None);
+        let mut other = packet(OP_DMX, 0, 0, &[1]);
+        other[0] = b'B';
+        assert_eq!(dmx(&other), None);
+        assert_eq!(dmx(HEADER), None);
+    }
+
+    #[test]
+    fn truncated_data_is_ignored() {
+        let mut truncated = packet(OP_DMX, 0, 0, &[1, 2, 3]);
+        truncated.pop();
+        assert_eq!(dmx(&truncated), None);
+    }
+}
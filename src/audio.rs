@@ -0,0 +1,118 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample};
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+use tracing::{error, info};
+use crate::OscHandler;
+
+/// Addresses of the audio envelopes, routed by `[[route]]`
+pub const PREFIX: &str = "/audio/";
+
+const BANDS: [&str; 4] = ["level", "bass", "mid", "treble"];
+/// Crossover frequencies of bass/mid and mid/treble, in Hz
+const CROSSOVERS: (f32, f32) = (250.0, 2000.0);
+const ATTACK: f32 = 0.01;
+const RELEASE: f32 = 0.2;
+
+/// Envelope follower of the whole signal and its bands
+struct Analyzer {
+    low_alpha: f32,
+    high_alpha: f32,
+    attack: f32,
+    release: f32,
+    /// Low-passed signal at each crossover
+    low: f32,
+    high: f32,
+    envelopes: [f32; BANDS.len()],
+}
+
+impl Analyzer {
+    fn new(sample_rate: f32) -> Self {
+        let alpha = |cutoff: f32| 1.0 - (-2.0 * PI * cutoff / sample_rate).exp();
+        let coefficient = |seconds: f32| 1.0 - (-1.0 / (seconds * sample_rate)).exp();
+        Analyzer {
+            low_alpha: alpha(CROSSOVERS.0),
+            high_alpha: alpha(CROSSOVERS.1),
+            attack: coefficient(ATTACK),
+            release: coefficient(RELEASE),
+            low: 0.0,
+            high: 0.0,
+            envelopes: [0.0; BANDS.len()],
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.low += self.low_alpha * (sample - self.low);
+        self.high += self.high_alpha * (sample - self.high);
+        let bands = [sample, self.low, self.high - self.low, sample - self.high];
+        for (envelope, band) in self.envelopes.iter_mut().zip(bands.iter()) {
+            let rectified = band.abs();
+            let coefficient = if rectified > *envelope { self.attack } else { self.release };
+            *envelope += coefficient * (rectified - *envelope);
+        }
+    }
+}
+
+/// Sends the envelopes of the audio input (`default` or part of the device name) each tick as OSC messages
+/// `/audio/level`, `/audio/bass` (below 250 Hz), `/audio/mid` and `/audio/treble` (above 2 kHz), when they change
+pub fn audio_listen(device_name: &str, osc_handler: OscHandler, tick_rate: f64) {
+    let host = cpal::default_host();
+    let device = if device_name == "default" {
+        host.default_input_device()
+    } else {
+        host.input_devices().expect("Invalid --audio-in: couldn't list devices")
+            .find(|device| device.name().is_ok_and(|name| name.contains(device_name)))
+    };
+    let device = device.unwrap_or_else(|| panic!("Invalid --audio-in: no such device {}", device_name));
+    let config = device.default_input_config().unwrap_or_else(|e| panic!("Invalid --audio-in: {}", e));
+    info!("[Audio] {} {:?}", device.name().unwrap_or_default(), config);
+    let envelopes: Arc<[AtomicU32; BANDS.len()]> = Arc::default();
+    let stream = match config.sample_format() {
+        SampleFormat::F32 => stream::<f32>(&device, &config.into(), envelopes.clone()),
+        SampleFormat::I16 => stream::<i16>(&device, &config.into(), envelopes.clone()),
+        SampleFormat::U16 => stream::<u16>(&device, &config.into(), envelopes.clone()),
+        format => panic!("Invalid --audio-in: unsupported sample format {:?}", format),
+    };
+    stream.play().unwrap_or_else(|e| panic!("Invalid --audio-in: {}", e));
+    let mut sent = [f32::NAN; BANDS.len()];
+    loop {
+        std::thread::sleep(Duration::from_secs_f64(1.0 / tick_rate));
+        for (i, band) in BANDS.iter().enumerate() {
+            let value = f32::from_bits(envelopes[i].load(Ordering::Relaxed)).min(1.0);
+            if (value - sent[i]).abs() < 0.001 {
+                continue;
+            }
+            sent[i] = value;
+            osc_handler.handle_packet(osc::Packet::Message(osc::Message {
+                addr: format!("{}{}", PREFIX, band),
+                args: Some(vec![OscType::Float(value)]),
+            }));
+        }
+    }
+}
+
+fn stream<T: SizedSample>(device: &cpal::Device, config: &cpal::StreamConfig, envelopes: Arc<[AtomicU32; BANDS.len()]>) -> cpal::Stream
+    where f32: FromSample<T>
+{
+    let channels = usize::from(config.channels).max(1);
+    let mut analyzer = Analyzer::new(config.sample_rate.0 as f32);
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            // mixed down to mono
+            for frame in data.chunks(channels) {
+                let sample = frame.iter().map(|sample| f32::from_sample_(*sample)).sum::<f32>() / frame.len() as f32;
+                analyzer.push(sample);
+            }
+            for (envelope, value) in envelopes.iter().zip(analyzer.envelopes.iter()) {
+                envelope.store(value.to_bits(), Ordering::Relaxed);
+            }
+        },
+        |e| error!("[Audio] {:?}", e),
+        None,
+    ).unwrap_or_else(|e| panic!("Invalid --audio-in: {}", e))
+}
@@ -0,0 +1,28 @@
+use std::sync::Arc;
+use std::time::Duration;
+use nannou_osc::rosc::OscType;
+use tracing::{debug, error};
+use crate::{Device, normalize_device_name, unique_devices};
+use crate::osc_send::OscSender;
+
+pub async fn report_battery_level(device: Device, osc_send: Arc<OscSender>) -> Result<(), ()> {
+    let device_name = normalize_device_name(device.name());
+    debug!("[{}] querying battery level", device_name);
+    let level = device.battery_level().await.map_err(|e|
+        error!("{:?}", e)
+    )?;
+    debug!("[{}] battery level {}", device_name, level);
+    osc_send.send(&format!("/devices/{}/battery/level", device_name), vec![OscType::Float(level as f32)]);
+    Ok(())
+}
+
+/// Periodically reports the battery level of every connected device which has a battery
+pub async fn battery_poll(interval: Duration, devices: evmap::ReadHandle<&'static str, Device>, osc_send: Arc<OscSender>) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        for device in unique_devices(&devices).into_iter().filter(|device| device.has_battery_level()) {
+            let _ = report_battery_level(device, osc_send.clone()).await;
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::path::Path;
+use regex::Regex;
+use url::Url;
+use crate::DEVICES_REGEX;
+use crate::config::{self, Config, Mappings, RouteConfig};
+use crate::patterns;
+use crate::shaping::Shaping;
+use crate::simulate::{self, SimulatedDevice};
+
+/// Errors in the --config file, found without connecting to anything
+pub fn check(path: &Path) -> Vec<String> {
+    let config = match config::parse(path) {
+        Ok(config) => config,
+        Err(e) => return vec![e],
+    };
+    let mut errors = Vec::new();
+    check_options(&config, &mut errors);
+    check_devices(&config, &mut errors);
+    if let Err(e) = Shaping::from_config(&config) {
+        errors.push(e);
+    }
+    // transforms, conditions and the selected profile
+    if let Err(e) = Mappings::from_config(&config) {
+        errors.push(e);
+    }
+    let routes = config.route.iter().map(|route| (String::from("route"), route))
+        .chain(config.profiles.iter().flat_map(|(name, profile)| {
+            profile.route.iter().map(move |route| (format!("profiles.{}.route", name), route))
+        }));
+    for (key, route) in routes {
+        check_route(&key, route, &mut errors);
+    }
+    let sets = config.aliases.iter().map(|(alias, set)| (format!("aliases.{}", alias), set))
+        .chain(config.group.iter().flat_map(|(group, members)| {
+            members.iter().map(move |member| (format!("group.{}", group), member))
+        }))
+        .chain(config.virtual_devices.iter().flat_map(|(name, members)| {
+            members.iter().map(move |member| (format!("virtual.{}", name), &member.device))
+        }));
+    for (key, set) in sets {
+        check_set(&key, set, &mut errors);
+    }
+    check_webhooks(&config, &mut errors);
+    errors
+}
+
+fn check_options(config: &Config, errors: &mut Vec<String>) {
+    for urls in config.intiface_connect.iter() {
+        for url in urls.split(',') {
+            match Url::parse(url.trim()) {
+                Ok(url) if url.scheme() == "ws" || url.scheme() == "wss" => {}
+                Ok(url) => errors.push(format!("intiface_connect: {} is not ws:// or wss://", url)),
+                Err(e) => errors.push(format!("intiface_connect: {}: {}", url, e)),
+            }
+        }
+    }
+    if let Some(ref url) = config.osc_listen {
+        if url.scheme() != "serial" && !(url.scheme() == "udp" && url.host().is_some() && url.port().is_some()) {
+            errors.push(format!("osc_listen: {} is not udp://<host>:<port> or serial://<path>", url));
+        }
+    }
+    for (key, url) in [
+        ("osc_send", &config.osc_send),
+        ("osc_forward", &config.osc_forward),
+        ("artnet_listen", &config.artnet_listen),
+    ].iter() {
+        if let Some(url) = url {
+            if !(url.scheme() == "udp" && url.host().is_some() && url.port().is_some()) {
+                errors.push(format!("{}: {} is not udp://<host>:<port>", key, url));
+            }
+        }
+    }
+    for (key, url) in [
+        ("oscquery_listen", &config.oscquery_listen),
+        ("metrics_listen", &config.metrics_listen),
+        ("dashboard_listen", &config.dashboard_listen),
+    ].iter() {
+        if let Some(url) = url {
+            if !(url.scheme() == "http" && url.host().is_some()) {
+                errors.push(format!("{}: {} is not http://<host>:<port>", key, url));
+            }
+        }
+    }
+    if let Some(ref url) = config.websocket_listen {
+        if !(url.scheme() == "ws" && url.host().is_some()) {
+            errors.push(format!("websocket_listen: {} is not ws://<host>:<port>", url));
+        }
+    }
+    if let Some(ref url) = config.mqtt_connect {
+        if !(url.scheme() == "mqtt" && url.host().is_some()) {
+            errors.push(format!("mqtt_connect: {} is not mqtt://<host>[:<port>]", url));
+        }
+    }
+    if let Some(ref namespace) = config.osc_namespace {
+        if !namespace.is_empty() && !namespace.starts_with('/') {
+            errors.push(String::from("osc_namespace: must start with /"));
+        }
+    }
+    for (key, path) in [("intiface_ca_file", &config.intiface_ca_file), ("spawn_intiface", &config.spawn_intiface)].iter() {
+        if let Some(path) = path {
+            if !path.is_file() {
+                errors.push(format!("{}: {} is not a file", key, path.display()));
+            }
+        }
+    }
+    if let Some(ref path) = config.funscript_dir {
+        if !path.is_dir() {
+            errors.push(format!("funscript_dir: {} is not a directory", path.display()));
+        }
+    }
+    let seconds = |value: Option<u64>| value.map(|value| value as f64);
+    for (key, value) in [
+        ("keepalive", config.keepalive),
+        ("keepalive_timeout", config.keepalive_timeout),
+        ("tick_rate", config.tick_rate),
+        ("osc_rate_limit", config.osc_rate_limit),
+        ("soft_start", config.soft_start),
+        ("battery_poll", seconds(config.battery_poll)),
+        ("heartbeat", seconds(config.heartbeat)),
+        ("watchdog", seconds(config.watchdog)),
+        ("session_limit", seconds(config.session_limit)),
+    ].iter() {
+        if let Some(Err(e)) = value.map(config::check_positive) {
+            errors.push(format!("{}: {}", key, e));
+        }
+    }
+    for (key, value) in [("max_intensity", config.max_intensity), ("toggle_speed", config.toggle_speed)].iter() {
+        if let Some(Err(e)) = value.map(config::check_speed) {
+            errors.push(format!("{}: {}", key, e));
+        }
+    }
+    if let Some(interval) = config.reconnect_max_interval {
+        if !interval.is_finite() || interval < 1.0 {
+            errors.push(String::from("reconnect_max_interval: must be at least 1"));
+        }
+    }
+    let mut simulated = Vec::new();
+    for device in config.simulate_device.iter() {
+        match device.parse::<SimulatedDevice>() {
+            Ok(device) => simulated.push(device),
+            Err(e) => errors.push(format!("simulate_device: {}: {}", device, e)),
+        }
+    }
+    if let Err(e) = simulate::check_names(&simulated) {
+        errors.push(format!("simulate_device: {}", e));
+    }
+    if let Some(ref log_level) = config.log_level {
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(log_level) {
+            errors.push(format!("log_level: {}", e));
+        }
+    }
+    if let Some(ref log_rotation) = config.log_rotation {
+        if !["minutely", "hourly", "daily", "never"].contains(&log_rotation.as_str()) {
+            errors.push(format!("log_rotation: {} is not minutely, hourly, daily or never", log_rotation));
+        }
+    }
+}
+
+fn check_devices(config: &Config, errors: &mut Vec<String>) {
+    let patterns = match patterns::load_patterns(config) {
+        Ok(patterns) => patterns,
+        Err(e) => {
+            errors.push(e);
+            return;
+        }
+    };
+    for (name, device) in config.device.iter() {
+        let key = format!("device.{}", name);
+        if let Some(ref pattern) = device.pattern {
+            if !patterns.contains_key(pattern) {
+                errors.push(format!("{}.pattern: unknown pattern {}", key, pattern));
+            }
+        }
+    }
+}
+
+/// The rest is validated by `Route::new` along with the mappings
+fn check_route(key: &str, route: &RouteConfig, errors: &mut Vec<String>) {
+    check_set(&format!("{} {}", key, route.address), &route.device, errors);
+}
+
+fn check_webhooks(config: &Config, errors: &mut Vec<String>) {
+    if let Some(ref url) = config.webhook_listen {
+        if !(url.scheme() == "http" && url.host().is_some()) {
+            errors.push(format!("webhook_listen: {} is not http://<host>:<port>", url));
+        }
+    }
+    // invalid ones reported with the devices
+    let patterns = patterns::load_patterns(config).unwrap_or_default();
+    for (event, webhook) in config.webhooks.iter() {
+        let key = format!("webhooks.{}", event);
+        match (&webhook.command, &webhook.pattern) {
+            (Some(_), Some(_)) | (None, None) => errors.push(format!("{}: needs either command or pattern", key)),
+            (None, Some(pattern)) if !patterns.is_empty() && !patterns.contains_key(pattern) => {
+                errors.push(format!("{}.pattern: unknown pattern {}", key, pattern));
+            }
+            _ => {}
+        }
+        check_set(&key, &webhook.device, errors);
+    }
+}
+
+/// Regex of the `re:` device set
+fn check_set(key: &str, set: &str, errors: &mut Vec<String>) {
+    if let Some(pattern) = set.strip_prefix(DEVICES_REGEX) {
+        if let Err(e) = Regex::new(pattern) {
+            errors.push(format!("{}: {}", key, e));
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use url::Url;
+use ipnet::IpNet;
+use crate::{Speed, Millis, FeatureIndex};
+use crate::shaping::Curve;
+use crate::routes::Route;
+
+/// Settings from the --config TOML file
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // options, overridden by the command line; --daemonize, --pidfile and --service are command line only,
+    // they take effect before the file is read
+    pub intiface_connect: Vec<String>,
+    pub intiface_ca_file: Option<PathBuf>,
+    pub intiface_insecure_tls: bool,
+    pub keepalive: Option<f64>,
+    pub keepalive_timeout: Option<f64>,
+    pub spawn_intiface: Option<PathBuf>,
+    pub spawn_intiface_arg: Vec<String>,
+    pub no_scan: bool,
+    pub client_name: Option<String>,
+    pub reconnect_max_interval: Option<f64>,
+    pub reconnect_max_attempts: Option<u32>,
+    pub osc_listen: Option<Url>,
+    pub osc_namespace: Option<String>,
+    pub osc_allow: Vec<IpNet>,
+    pub osc_rate_limit: Option<f64>,
+    pub midi_in: Option<String>,
+    pub gamepad: bool,
+    pub artnet_listen: Option<Url>,
+    pub audio_in: Option<String>,
+    pub mqtt_connect: Option<Url>,
+    pub mqtt_topic: Option<String>,
+    pub no_mqtt_discovery: bool,
+    pub websocket_listen: Option<Url>,
+    pub metrics_listen: Option<Url>,
+    pub webhook_listen: Option<Url>,
+    pub dashboard_listen: Option<Url>,
+    pub stdio: bool,
+    pub osc_send: Option<Url>,
+    pub osc_forward: Option<Url>,
+    pub osc_forward_unmatched: bool,
+    pub osc_dump: bool,
+    pub oscquery_listen: Option<Url>,
+    pub battery_poll: Option<u64>,
+    pub heartbeat: Option<u64>,
+    pub watchdog: Option<u64>,
+    pub session_limit: Option<u64>,
+    pub allow_raw: bool,
+    pub funscript_dir: Option<PathBuf>,
+    pub dump_address_space: Option<PathBuf>,
+    pub max_intensity: Option<Speed>,
+    pub soft_start: Option<f64>,
+    pub toggle_speed: Option<Speed>,
+    pub tick_rate: Option<f64>,
+    pub mdns: bool,
+    pub vrchat: bool,
+    pub simulate_device: Vec<String>,
+    pub log_level: Option<String>,
+    pub log_file: Option<PathBuf>,
+    pub tui: bool,
+    pub log_rotation: Option<String>,
+
+    /// User-defined patterns by name, in addition to the built-in ones
+    pub patterns: HashMap<String, PatternConfig>,
+    /// Per-device settings by normalized device name, as in the log output
+    pub device: HashMap<String, DeviceConfig>,
+    /// Stable names for the devices, e.g. `left = "LovenseHush"`
+    pub aliases: HashMap<String, String>,
+    /// Names (or aliases) of the devices addressed together, e.g. `group.legs = ["HushA", "HushB"]`
+    pub group: HashMap<String, Vec<String>>,
+    /// Names (or aliases) of the devices addressed together with scaled speeds,
+    /// e.g. `virtual.combo = [{ device = "HushA", weight = 1.0 }, { device = "Edge", weight = 0.5 }]`
+    #[serde(rename = "virtual")]
+    pub virtual_devices: HashMap<String, Vec<VirtualMember>>,
+    /// Only devices matching these name patterns (`*` is a wildcard) are added
+    pub device_allow: Vec<String>,
+    /// Devices matching these name patterns are never added
+    pub device_block: Vec<String>,
+    /// What the `last` device is: the recently (re)connected one, or the one addressed by the latest command
+    pub last: LastSelector,
+    /// Default lowest non-zero vibration speed sent to the devices
+    pub min_speed: Option<Speed>,
+    /// Default highest vibration speed sent to the devices
+    pub max_speed: Option<Speed>,
+    /// Default largest change of the vibration speed per second, larger jumps are ramped
+    pub slew_rate: Option<f64>,
+    /// Arbitrary addresses mapped to the device commands
+    pub route: Vec<RouteConfig>,
+    /// VRChat avatar parameters mapped to the vibration speed of the devices, e.g. `Squeeze = "HushA"`
+    pub avatar_parameters: HashMap<String, String>,
+    /// Routes and avatar parameters active in addition to the ones above while the profile is selected,
+    /// e.g. `[[profiles.game.route]]`
+    pub profiles: HashMap<String, ProfileConfig>,
+    /// Profile selected at the start, until `/profile/select`
+    pub profile: Option<String>,
+    /// Events received by --webhook-listen mapped to the device commands
+    pub webhooks: HashMap<String, WebhookConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub route: Vec<RouteConfig>,
+    pub avatar_parameters: HashMap<String, String>,
+}
+
+/// ```toml
+/// [device.LovenseHush]
+/// curve = 2.2  # or [[0.0, 0.0], [0.5, 0.3], [1.0, 1.0]]
+/// min_speed = 0.2
+/// max_speed = 0.8
+/// pattern = "wave"
+/// motors = { 0 = 1, 1 = 0 }
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub curve: Option<Curve>,
+    pub min_speed: Option<Speed>,
+    pub max_speed: Option<Speed>,
+    pub slew_rate: Option<f64>,
+    /// Played whenever the device appears
+    pub pattern: Option<String>,
+    /// Motor index in the OSC messages => motor index of the device
+    pub motors: HashMap<String, FeatureIndex>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum LastSelector {
+    #[default]
+    Added,
+    Commanded,
+}
+
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualMember {
+    pub device: String,
+    /// Multiplies the speeds given in the OSC messages
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+/// ```toml
+/// [webhooks.follow]
+/// device = "HushA"
+/// command = "vibrate/pulse"
+/// args = [1.0, 2000]
+///
+/// [webhooks.raid]
+/// pattern = "escalate"
+/// ```
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Name, alias, group or any other devices set, as in `/devices/<set>/...`
+    #[serde(default = "default_webhook_device")]
+    pub device: String,
+    /// As in `/devices/<set>/<command>`, e.g. `vibrate/speed`
+    pub command: Option<String>,
+    #[serde(default)]
+    pub args: Vec<toml::Value>,
+    /// Named pattern to play instead of the command
+    pub pattern: Option<String>,
+}
+
+fn default_webhook_device() -> String {
+    String::from(crate::DEVICES_ALL)
+}
+
+/// ```toml
+/// [[route]]
+/// match = "/avatar/parameters/Squeeze"  # `*` is a wildcard
+/// device = "HushA"
+/// command = "vibrate/speed"
+/// input = [0, 127]
+/// deadzone = 0.05
+/// threshold = 0.1
+/// transform = "x * x * (1.0 - cos(t))"
+/// scale = [0.0, 1.0]
+/// smoothing = 0.2
+/// min_interval_ms = 50
+/// min_change = 0.02
+/// when = "/avatar/parameters/HapticsEnabled == 1"
+/// combine = "max"
+/// ```
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    #[serde(rename = "match")]
+    pub address: String,
+    /// Name, alias, group or any other devices set, as in `/devices/<set>/...`
+    pub device: String,
+    /// Address under `/devices/<set>/`, e.g. `vibrate/speed`
+    pub command: String,
+    /// Range of the first argument, remapped to 0..1 with clamping; taken as 0..1 without clamping by default
+    #[serde(default)]
+    pub input: Option<(f64, f64)>,
+    /// Values (0..1) this close to zero are sent as zero, the rest is stretched back to 0..1
+    #[serde(default)]
+    pub deadzone: f64,
+    /// [Rhai](https://rhai.rs/) expression of `x` (0..1, after the deadzone), `t` (seconds since the start)
+    /// and `params` (latest values by the last address segment, e.g. `params.Squeeze`)
+    #[serde(default)]
+    pub transform: Option<String>,
+    /// Values (0..1, after the deadzone and transform) below this are sent as zero
+    #[serde(default)]
+    pub threshold: f64,
+    /// Range the first argument (0..1) is mapped to
+    #[serde(default = "default_scale")]
+    pub scale: (f64, f64),
+    /// Time constant in seconds of the low-pass filter the first argument approaches the received value with,
+    /// sent each tick (see --tick-rate); zero for none
+    #[serde(default)]
+    pub smoothing: f64,
+    /// Sent at most once per this many milliseconds, the latest one of the skipped is sent afterwards
+    #[serde(default)]
+    pub min_interval_ms: Millis,
+    /// Skipped unless the first argument differs from the last sent one by at least this much (zero always passes)
+    #[serde(default)]
+    pub min_change: f64,
+    /// Routed only while the latest received value of the address compares so (`==`, `!=`, `<`, `<=`, `>`, `>=`),
+    /// bool as 0 and 1; never before the first value
+    #[serde(default)]
+    pub when: Option<String>,
+    /// How the first argument is combined with the latest ones routed from the other addresses
+    /// to the same device and command
+    #[serde(default)]
+    pub combine: Combine,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum Combine {
+    #[default]
+    Latest,
+    Max,
+    /// Clamped to -1..1
+    Sum,
+    Average,
+}
+
+
+fn default_scale() -> (f64, f64) {
+    (0.0, 1.0)
+}
+
+impl RouteConfig {
+    /// Received first argument remapped to 0..1, after the deadzone
+    pub fn normalize(&self, value: f64) -> f64 {
+        let value = match self.input {
+            Some((min, max)) if min != max => ((value - min) / (max - min)).max(0.0).min(1.0),
+            _ => value,
+        };
+        if self.deadzone > 0.0 && self.deadzone < 1.0 {
+            value.signum() * ((value.abs() - self.deadzone) / (1.0 - self.deadzone)).max(0.0)
+        } else {
+            value
+        }
+    }
+
+    /// First argument of the routed message for the normalized (and transformed) one
+    pub fn scale(&self, value: f64) -> f64 {
+        // zero stops the device regardless of the scale
+        if value == 0.0 || value.abs() < self.threshold {
+            return 0.0;
+        }
+        self.scale.0 + value * (self.scale.1 - self.scale.0)
+    }
+}
+
+/// ```toml
+/// [patterns.stairs]
+/// steps = [[0.2, 1000], [0.5, 1000], [0.8, 1000]]
+/// smooth = false
+/// repeat = true
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatternConfig {
+    /// Speed to reach and duration in milliseconds to hold (or, if smooth, to reach) it
+    pub steps: Vec<(Speed, Millis)>,
+    #[serde(default)]
+    pub smooth: bool,
+    #[serde(default = "default_true")]
+    pub repeat: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Names and routes resolved by the OSC handler, replaced when the --config file is reloaded
+#[derive(Default)]
+pub struct Mappings {
+    pub aliases: HashMap<String, String>,
+    pub groups: HashMap<String, Vec<String>>,
+    pub virtual_devices: HashMap<String, Vec<VirtualMember>>,
+    routes: Vec<Route>,
+    profiles: HashMap<String, Vec<Route>>,
+    profile: Option<String>,
+}
+
+impl Mappings {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let profiles = config.profiles.iter()
+            .map(|(name, profile)| Ok((name.clone(), routes(&profile.route, &profile.avatar_parameters)?)))
+            .collect::<Result<HashMap<String, Vec<Route>>, String>>()?;
+        if let Some(ref profile) = config.profile {
+            if !profiles.contains_key(profile) {
+                return Err(format!("unknown profile {}", profile));
+            }
+        }
+        Ok(Mappings {
+            aliases: config.aliases.clone(),
+            groups: config.group.clone(),
+            virtual_devices: config.virtual_devices.clone(),
+            routes: routes(&config.route, &config.avatar_parameters)?,
+            profiles,
+            profile: config.profile.clone(),
+        })
+    }
+
+    /// Routes of the selected profile, and the ones active regardless
+    pub fn routes(&self) -> impl Iterator<Item = &Route> {
+        let profile = self.profile.as_ref().and_then(|profile| self.profiles.get(profile));
+        self.routes.iter().chain(profile.into_iter().flatten())
+    }
+
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Switches the routes atomically, false if there is no such profile
+    pub fn select_profile(&mut self, profile: &str) -> bool {
+        if !self.profiles.contains_key(profile) {
+            return false;
+        }
+        self.profile = Some(String::from(profile));
+        true
+    }
+}
+
+fn routes(routes: &[RouteConfig], avatar_parameters: &HashMap<String, String>) -> Result<Vec<Route>, String> {
+    routes.iter().cloned()
+        .chain(crate::vrchat::routes(avatar_parameters))
+        .map(Route::new)
+        .collect()
+}
+
+/// Err unless the value is positive, e.g. the seconds between the polls; shared by the options and `check`
+pub fn check_positive(value: f64) -> Result<(), &'static str> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err("must be positive")
+    }
+}
+
+/// Err unless the value is a speed within 0..1; shared by the options and `check`
+pub fn check_speed(value: Speed) -> Result<(), &'static str> {
+    if (0.0..=1.0).contains(&value) {
+        Ok(())
+    } else {
+        Err("must be within 0..1")
+    }
+}
+
+pub fn parse(path: &Path) -> Result<Config, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    toml::from_str(&content).map_err(|e| e.to_string())
+}
+
+pub fn load(path: &Path) -> Config {
+    parse(path).unwrap_or_else(|e| panic!("Invalid --config: {}", e))
+}
@@ -0,0 +1,13 @@
+use std::path::Path;
+use anyhow::{anyhow, Result};
+use daemonize::Daemonize;
+
+/// Forks into the background, keeping the working directory for the relative paths; the output is discarded,
+/// so the log only goes to --log-file
+pub fn daemonize(pidfile: Option<&Path>) -> Result<()> {
+    let mut daemonize = Daemonize::new().working_directory(std::env::current_dir()?);
+    if let Some(pidfile) = pidfile {
+        daemonize = daemonize.pid_file(pidfile);
+    }
+    daemonize.start().map_err(|e| anyhow!("Invalid --daemonize: {}", e))
+}
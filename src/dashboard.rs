@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use nannou_osc as osc;
+use serde::Serialize;
+use tracing::{debug, warn};
+use crate::{DEVICES_LAST, OscHandler};
+use crate::intiface::ServerStatus;
+use crate::metrics::METRICS;
+use crate::websocket::{self, JsonMessage};
+
+const PAGE: &str = include_str!("dashboard.html");
+/// Received OSC messages shown
+const TRAFFIC_SIZE: usize = 50;
+
+/// Latest OSC messages, recorded only with --dashboard-listen
+#[derive(Default)]
+pub struct Traffic {
+    messages: Mutex<VecDeque<JsonMessage>>,
+}
+
+impl Traffic {
+    pub fn record(&self, message: &osc::Message) {
+        let args = message.args.iter().flatten().map(websocket::json_arg).collect();
+        let mut messages = self.messages.lock().expect("unexpected");
+        if messages.len() == TRAFFIC_SIZE {
+            messages.pop_front();
+        }
+        messages.push_back(JsonMessage { address: message.addr.clone(), args });
+    }
+}
+
+#[derive(Serialize)]
+struct State<'a> {
+    servers: Vec<ServerState>,
+    devices: Vec<DeviceState>,
+    /// Latest first
+    traffic: Vec<&'a JsonMessage>,
+    last_source: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ServerState {
+    name: String,
+    address: String,
+    state: &'static str,
+}
+
+#[derive(Serialize)]
+struct DeviceState {
+    name: &'static str,
+    speed: f64,
+}
+
+/// Serves the page at `/`, its state at `/state`, and takes the OSC messages as JSON (without --osc-namespace)
+/// posted to `/send`
+pub fn dashboard_listen(host_port: &str, osc_handler: OscHandler, server_status: Arc<ServerStatus>, traffic: Arc<Traffic>) {
+    let server = tiny_http::Server::http(host_port).expect("Invalid --dashboard-listen: couldn't bind socket");
+    let header = |content_type: &[u8]| tiny_http::Header::from_bytes(&b"Content-Type"[..], content_type).expect("unexpected");
+    for mut request in server.incoming_requests() {
+        debug!("[Dashboard] {} {}", request.method(), request.url());
+        let result = match (request.method(), request.url()) {
+            (tiny_http::Method::Get, "/") => {
+                request.respond(tiny_http::Response::from_string(PAGE).with_header(header(b"text/html; charset=utf-8")))
+            }
+            (tiny_http::Method::Get, "/state") => {
+                let body = state(&osc_handler, &server_status, &traffic);
+                request.respond(tiny_http::Response::from_string(body).with_header(header(b"application/json")))
+            }
+            (tiny_http::Method::Post, "/send") => {
+                let mut body = String::new();
+                let message = request.as_reader().read_to_string(&mut body).map_err(|e| e.to_string())
+                    .and_then(|_| serde_json::from_str::<JsonMessage>(&body).map_err(|e| e.to_string()))
+                    .and_then(websocket::osc_message);
+                match message {
+                    Ok(mut message) => {
+                        message.addr = format!("{}{}", osc_handler.namespace, message.addr);
+                        osc_handler.handle_packet(osc::Packet::Message(message));
+                        request.respond(tiny_http::Response::empty(204))
+                    }
+                    Err(e) => {
+                        warn!("[Dashboard] {}", e);
+                        request.respond(tiny_http::Response::from_string(e).with_status_code(400))
+                    }
+                }
+            }
+            _ => request.respond(tiny_http::Response::empty(404)),
+        };
+        if let Err(e) = result {
+            warn!("[Dashboard] {:?}", e);
+        }
+    }
+}
+
+fn state(osc_handler: &OscHandler, server_status: &ServerStatus, traffic: &Traffic) -> String {
+    let servers = server_status.states().into_iter()
+        .map(|(name, address, state)| ServerState { name, address, state })
+        .collect();
+    let mut names: Vec<&'static str> = osc_handler.devices.read()
+        .map(|map| map.iter().map(|(name, _)| *name).collect())
+        .unwrap_or_default();
+    // <server>/<name> are the same devices
+    names.retain(|name| *name != DEVICES_LAST && !name.contains('/'));
+    names.sort_unstable();
+    let devices = names.into_iter()
+        .map(|name| DeviceState { name, speed: osc_handler.states.speed(name) })
+        .collect();
+    let messages = traffic.messages.lock().expect("unexpected");
+    serde_json::to_string(&State {
+        servers,
+        devices,
+        traffic: messages.iter().rev().collect(),
+        last_source: METRICS.last_source(),
+    }).expect("unexpected")
+}
@@ -0,0 +1,85 @@
+use std::fmt::Debug;
+use buttplug::client::{ButtplugClientDevice, ButtplugClientDeviceEvent, ButtplugClientError};
+use buttplug::client::device::{ScalarValueCommand, ScalarCommand, RotateCommand, LinearCommand};
+use buttplug::core::message::{ClientDeviceMessageAttributes, Endpoint, SensorType};
+use futures_util::future::BoxFuture;
+use futures_util::stream::Stream;
+
+pub type DeviceResultFuture<T = ()> = BoxFuture<'static, Result<T, ButtplugClientError>>;
+
+/// What the bridge commands: a device of an Intiface server, or a --simulate-device;
+/// the same as the methods of `ButtplugClientDevice`
+pub trait DeviceBackend: Debug + Send + Sync {
+    fn name(&self) -> &String;
+    fn index(&self) -> u32;
+    fn message_attributes(&self) -> &ClientDeviceMessageAttributes;
+    fn vibrate(&self, command: &ScalarValueCommand) -> DeviceResultFuture;
+    fn scalar(&self, command: &ScalarCommand) -> DeviceResultFuture;
+    fn rotate(&self, command: &RotateCommand) -> DeviceResultFuture;
+    fn linear(&self, command: &LinearCommand) -> DeviceResultFuture;
+    fn stop(&self) -> DeviceResultFuture;
+    fn has_battery_level(&self) -> bool;
+    fn battery_level(&self) -> DeviceResultFuture<f64>;
+    fn raw_write(&self, endpoint: Endpoint, data: &[u8], write_with_response: bool) -> DeviceResultFuture;
+    fn event_stream(&self) -> Box<dyn Stream<Item = ButtplugClientDeviceEvent> + Send + Unpin>;
+    fn subscribe_sensor(&self, sensor_index: u32, sensor_type: SensorType) -> DeviceResultFuture;
+    fn unsubscribe_sensor(&self, sensor_index: u32, sensor_type: SensorType) -> DeviceResultFuture;
+}
+
+impl DeviceBackend for ButtplugClientDevice {
+    fn name(&self) -> &String {
+        ButtplugClientDevice::name(self)
+    }
+
+    fn index(&self) -> u32 {
+        ButtplugClientDevice::index(self)
+    }
+
+    fn message_attributes(&self) -> &ClientDeviceMessageAttributes {
+        ButtplugClientDevice::message_attributes(self)
+    }
+
+    fn vibrate(&self, command: &ScalarValueCommand) -> DeviceResultFuture {
+        ButtplugClientDevice::vibrate(self, command)
+    }
+
+    fn scalar(&self, command: &ScalarCommand) -> DeviceResultFuture {
+        ButtplugClientDevice::scalar(self, command)
+    }
+
+    fn rotate(&self, command: &RotateCommand) -> DeviceResultFuture {
+        ButtplugClientDevice::rotate(self, command)
+    }
+
+    fn linear(&self, command: &LinearCommand) -> DeviceResultFuture {
+        ButtplugClientDevice::linear(self, command)
+    }
+
+    fn stop(&self) -> DeviceResultFuture {
+        ButtplugClientDevice::stop(self)
+    }
+
+    fn has_battery_level(&self) -> bool {
+        ButtplugClientDevice::has_battery_level(self)
+    }
+
+    fn battery_level(&self) -> DeviceResultFuture<f64> {
+        ButtplugClientDevice::battery_level(self)
+    }
+
+    fn raw_write(&self, endpoint: Endpoint, data: &[u8], write_with_response: bool) -> DeviceResultFuture {
+        ButtplugClientDevice::raw_write(self, endpoint, data, write_with_response)
+    }
+
+    fn event_stream(&self) -> Box<dyn Stream<Item = ButtplugClientDeviceEvent> + Send + Unpin> {
+        ButtplugClientDevice::event_stream(self)
+    }
+
+    fn subscribe_sensor(&self, sensor_index: u32, sensor_type: SensorType) -> DeviceResultFuture {
+        ButtplugClientDevice::subscribe_sensor(self, sensor_index, sensor_type)
+    }
+
+    fn unsubscribe_sensor(&self, sensor_index: u32, sensor_type: SensorType) -> DeviceResultFuture {
+        ButtplugClientDevice::unsubscribe_sensor(self, sensor_index, sensor_type)
+    }
+}
@@ -0,0 +1,60 @@
+/// Which devices are added to the registry, by normalized name patterns with `*` wildcards
+#[derive(Default)]
+pub struct DeviceFilter {
+    allow: Vec<String>,
+    block: Vec<String>,
+}
+
+impl DeviceFilter {
+    pub fn new(allow: Vec<String>, block: Vec<String>) -> Self {
+        DeviceFilter { allow, block }
+    }
+
+    /// Allowed (or no allowlist) and not blocked
+    pub fn accepts(&self, name: &str) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|pattern| wildcard_match(pattern, name)))
+            && !self.block.iter().any(|pattern| wildcard_match(pattern, name))
+    }
+}
+
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            match name.strip_prefix(prefix) {
+                Some(name) => (0..=name.len())
+                    .filter(|&i| name.is_char_boundary(i))
+                    .any(|i| wildcard_match(rest, &name[i..])),
+                None => false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("LovenseHush", "LovenseHush"));
+        assert!(!wildcard_match("LovenseHush", "LovenseHush2"));
+        assert!(wildcard_match("Lovense*", "LovenseHush"));
+        assert!(wildcard_match("*Hush", "LovenseHush"));
+        assert!(wildcard_match("L*e*H*", "LovenseHush"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("We*", "LovenseHush"));
+        assert!(!wildcard_match("*Edge", "LovenseHush"));
+        assert!(wildcard_match("/avatar/parameters/*", "/avatar/parameters/Squeeze"));
+        assert!(wildcard_match("Vibe*ö", "Vibeßö"));
+    }
+
+    #[test]
+    fn block_wins_over_allow() {
+        let filter = DeviceFilter::new(vec![String::from("Lovense*")], vec![String::from("*Edge")]);
+        assert!(filter.accepts("LovenseHush"));
+        assert!(!filter.accepts("LovenseEdge"));
+        assert!(!filter.accepts("WeVibeVector"));
+        assert!(DeviceFilter::default().accepts("WeVibeVector"));
+    }
+}
@@ -0,0 +1,29 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use nannou_osc::rosc::OscType;
+use tracing::debug;
+use crate::{Device, normalize_device_name};
+use crate::osc_send::OscSender;
+
+/// Replies with one message per actuator type, e.g. `/devices/<name>/features/Vibrate 2 20 20`:
+/// feature count and the step count of each feature
+pub fn report_features(device: &Device, osc_send: &Arc<OscSender>) {
+    let device_name = normalize_device_name(device.name());
+    let attributes = device.message_attributes();
+    let mut features: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    for attribute in attributes.scalar_cmd().iter().flatten() {
+        features.entry(format!("{:?}", attribute.actuator_type())).or_default().push(*attribute.step_count());
+    }
+    for attribute in attributes.rotate_cmd().iter().flatten() {
+        features.entry(String::from("Rotate")).or_default().push(*attribute.step_count());
+    }
+    for attribute in attributes.linear_cmd().iter().flatten() {
+        features.entry(String::from("Linear")).or_default().push(*attribute.step_count());
+    }
+    for (actuator_type, step_counts) in features {
+        let mut args = vec![OscType::Int(step_counts.len() as i32)];
+        args.extend(step_counts.iter().map(|steps| OscType::Int(*steps as i32)));
+        debug!("[{}] {} {:?}", device_name, actuator_type, args);
+        osc_send.send(&format!("/devices/{}/features/{}", device_name, actuator_type), args);
+    }
+}
@@ -0,0 +1,93 @@
+// https://github.com/funjack/launchcontrol/blob/master/protocol/funscript/README.md
+
+use std::path::{Component, Path};
+use std::time::Duration;
+use serde::Deserialize;
+use tokio::time;
+use buttplug::client::device::LinearCommand;
+use tracing::error;
+use crate::{Device, Millis};
+use crate::state::{self, DeviceStates};
+
+#[derive(Deserialize)]
+pub struct Funscript {
+    #[serde(default)]
+    inverted: bool,
+    actions: Vec<Action>,
+}
+
+#[derive(Deserialize)]
+struct Action {
+    /// Milliseconds from the start
+    at: Millis,
+    /// From 0 to 100
+    pos: u8,
+}
+
+impl Funscript {
+    /// Name of the file (with or without .funscript extension) in the `dir`, which nothing outside of can be read:
+    /// the name is relative, without `..`
+    pub fn load(name: &str, dir: Option<&Path>) -> Result<Self, String> {
+        let dir = dir.ok_or_else(|| String::from("no --funscript-dir"))?;
+        let name = Path::new(name);
+        if name.components().count() == 0 || !name.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(format!("{}: not a name in --funscript-dir", name.display()));
+        }
+        let path = dir.join(name);
+        let path = if path.extension().is_some() { path } else { path.with_extension("funscript") };
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut funscript: Funscript = serde_json::from_str(&content)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        funscript.actions.sort_by_key(|action| action.at);
+        Ok(funscript)
+    }
+
+    fn position(&self, action: &Action) -> f64 {
+        let pos = f64::from(action.pos.min(100)) / 100.0;
+        if self.inverted { 1.0 - pos } else { pos }
+    }
+}
+
+/// Moves the linear actuators through the positions of the script starting `offset` milliseconds in,
+/// or vibrates with the position as speed if the device has no linear actuators
+pub async fn play(device: Device, states: DeviceStates, funscript: std::sync::Arc<Funscript>, offset: Millis) {
+    let linear = device.message_attributes().linear_cmd().is_some();
+    let start = time::Instant::now() - Duration::from_millis(offset.into());
+    let mut previous_at = offset;
+    for action in funscript.actions.iter().filter(|action| action.at >= offset) {
+        let position = funscript.position(action);
+        if linear {
+            // moving since the previous action to arrive at this one in time
+            time::sleep_until(start + Duration::from_millis(previous_at.into())).await;
+            let _ = device.linear(&LinearCommand::Linear(action.at - previous_at, position)).await.map_err(|e|
+                error!("{:?}", e)
+            );
+        } else {
+            time::sleep_until(start + Duration::from_millis(action.at.into())).await;
+            let _ = state::vibrate(&device, &states, position).await;
+        }
+        previous_at = action.at;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_names_in_the_dir_are_loaded() {
+        let dir = std::env::temp_dir().join(format!("buttplug-osc-funscript-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).expect("unexpected");
+        std::fs::write(dir.join("nested/wave.funscript"), r#"{"actions": [{"at": 100, "pos": 50}, {"at": 0, "pos": 0}]}"#)
+            .expect("unexpected");
+        let loaded = Funscript::load("nested/wave", Some(&dir)).expect("unexpected");
+        assert_eq!(loaded.actions.iter().map(|action| action.at).collect::<Vec<_>>(), vec![0, 100]);
+        assert!(Funscript::load("nested/wave", None).is_err());
+        let outside = dir.join("nested/wave.funscript");
+        assert!(Funscript::load(&outside.to_string_lossy(), Some(&dir.join("nested"))).is_err());
+        assert!(Funscript::load("../nested/wave", Some(&dir.join("nested"))).is_err());
+        assert!(Funscript::load("", Some(&dir)).is_err());
+        std::fs::remove_dir_all(&dir).expect("unexpected");
+    }
+}
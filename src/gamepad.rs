@@ -0,0 +1,43 @@
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+use gilrs::{EventType, Gilrs};
+use tracing::{debug, info};
+use crate::OscHandler;
+
+/// Addresses of the gamepad inputs, routed by `[[route]]`
+pub const PREFIX: &str = "/gamepad/";
+
+/// Receives the gamepad inputs as OSC messages: `/gamepad/<id>/axis/<axis> <value>`, -1..1,
+/// and `/gamepad/<id>/button/<button> <value>`, 0..1 (analog for the triggers);
+/// names as in https://docs.rs/gilrs/0.10/gilrs/ev/enum.Axis.html and .../enum.Button.html, e.g. LeftStickX, RightTrigger2
+pub fn gamepad_listen(osc_handler: OscHandler) {
+    let mut gilrs = Gilrs::new().expect("Invalid --gamepad: couldn't initialize gamepad input");
+    for (id, gamepad) in gilrs.gamepads() {
+        info!("[Gamepad {}] {}", id, gamepad.name());
+    }
+    loop {
+        let event = match gilrs.next_event_blocking(None) {
+            Some(event) => event,
+            None => continue,
+        };
+        let (kind, name, value) = match event.event {
+            EventType::AxisChanged(axis, value, _) => ("axis", format!("{:?}", axis), value),
+            EventType::ButtonChanged(button, value, _) => ("button", format!("{:?}", button), value),
+            EventType::Connected => {
+                info!("[Gamepad {}] connected", event.id);
+                continue;
+            }
+            EventType::Disconnected => {
+                info!("[Gamepad {}] disconnected", event.id);
+                continue;
+            }
+            _ => continue,
+        };
+        let addr = format!("{}{}/{}/{}", PREFIX, event.id, kind, name);
+        debug!("[Gamepad] {} {}", addr, value);
+        osc_handler.handle_packet(osc::Packet::Message(osc::Message {
+            addr,
+            args: Some(vec![OscType::Float(value)]),
+        }));
+    }
+}
@@ -0,0 +1,26 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use nannou_osc::rosc::OscType;
+use crate::{Device, unique_devices};
+use crate::intiface::ServerStatus;
+use crate::osc_send::OscSender;
+
+/// Sends `/status/heartbeat <uptime> <device count> <connected>` periodically, so the controllers can tell that
+/// the bridge is gone when it stops
+pub async fn heartbeat(
+    interval: Duration,
+    devices: evmap::ReadHandle<&'static str, Device>,
+    server_status: Arc<ServerStatus>,
+    osc_send: Arc<OscSender>,
+) {
+    let start = Instant::now();
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        osc_send.send("/status/heartbeat", vec![
+            OscType::Int(start.elapsed().as_secs() as i32),
+            OscType::Int(unique_devices(&devices).len() as i32),
+            OscType::Bool(server_status.connected()),
+        ]);
+    }
+}
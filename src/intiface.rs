@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use futures_util::stream::StreamExt;
+use nannou_osc::rosc::OscType;
+use url::Url;
+use buttplug::{
+    client::{ButtplugClient, ButtplugClientEvent},
+    core::{
+        connector::{ButtplugRemoteClientConnector, ButtplugWebsocketClientTransport},
+        message::serializer::ButtplugClientJSONSerializer,
+    },
+};
+use anyhow::{bail, Result, Error};
+use rand::Rng;
+use tracing::{debug, info, warn, error};
+use crate::{Device, DEVICES_LAST, normalize_device_name};
+use crate::device_filter::DeviceFilter;
+use crate::metrics::METRICS;
+use crate::osc_send::OscSender;
+use crate::patterns::DefaultPatterns;
+use crate::state::DeviceStates;
+
+/// Currently connected Intiface clients
+pub type Clients = Arc<Mutex<Vec<Arc<ButtplugClient>>>>;
+
+/// While connected to a backup server, how often to check whether the primary one is back
+const PRIMARY_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Intiface server from --intiface-connect, with the backups to fail over to
+#[derive(Clone)]
+pub struct Server {
+    /// URL fragment or host of the primary, e.g. `partner` of `wss://example.com:12345#partner`
+    pub name: String,
+    /// Primary first
+    addresses: Vec<Address>,
+}
+
+#[derive(Clone)]
+struct Address {
+    url: String,
+    tls: bool,
+}
+
+impl Server {
+    /// Comma-separated URLs, primary first
+    pub fn new(urls: &str) -> Self {
+        let urls: Vec<Url> = urls.split(',')
+            .map(|url| Url::parse(url.trim()).unwrap_or_else(|e| panic!("Invalid --intiface-connect: {}", e)))
+            .collect();
+        let name = match urls[0].fragment() {
+            Some(fragment) if !fragment.is_empty() => normalize_device_name(fragment),
+            _ => urls[0].host_str().map(normalize_device_name).unwrap_or_default(),
+        };
+        if name.is_empty() {
+            panic!("Invalid --intiface-connect: name the server by URL fragment, e.g. ws://host:12345#name");
+        }
+        let addresses = urls.into_iter().map(|mut url| {
+            let tls = match url.scheme() {
+                "ws" => false,
+                "wss" => true,
+                _ => panic!("Invalid --intiface-connect: only ws:// and wss:// are supported"),
+            };
+            url.set_fragment(None);
+            Address { url: String::from(url.as_str()), tls }
+        }).collect();
+        Server { name, addresses }
+    }
+
+    pub fn primary_port(&self) -> Option<u16> {
+        Url::parse(&self.addresses[0].url).ok()?.port_or_known_default()
+    }
+
+    /// Devices found by the primary within the scan time, e.g. to generate a layout without the bridge running
+    pub async fn scan(&self, client_name: &str, insecure_tls: bool, scan_time: Duration) -> Result<Vec<Device>> {
+        let client = ButtplugClient::new(client_name);
+        client.connect(connector(&self.addresses[0], insecure_tls)).await?;
+        client.start_scanning().await?;
+        tokio::time::sleep(scan_time).await;
+        let _ = client.stop_scanning().await;
+        let devices = client.devices().into_iter().map(Device::from).collect();
+        let _ = client.disconnect().await;
+        Ok(devices)
+    }
+}
+
+fn connector(address: &Address, insecure_tls: bool) -> ButtplugRemoteClientConnector<ButtplugWebsocketClientTransport, ButtplugClientJSONSerializer> {
+    let transport = if address.tls {
+        ButtplugWebsocketClientTransport::new_secure_connector(&address.url, insecure_tls)
+    } else {
+        ButtplugWebsocketClientTransport::new_insecure_connector(&address.url)
+    };
+    ButtplugRemoteClientConnector::new(transport)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ServerState {
+    Disconnected,
+    Connected,
+    Scanning,
+}
+
+impl ServerState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ServerState::Disconnected => "disconnected",
+            ServerState::Connected => "connected",
+            ServerState::Scanning => "scanning",
+        }
+    }
+}
+
+/// Connection state of each server, sent via --osc-send as `/status/server/<server> <state> <url>`
+/// whenever it changes, and on `/status`
+pub struct ServerStatus {
+    /// server name => (address, state)
+    states: Mutex<BTreeMap<String, (String, ServerState)>>,
+    /// Whether the connected servers scan for devices
+    scan: AtomicBool,
+}
+
+impl ServerStatus {
+    pub fn new(servers: &[Server], scan: bool) -> Self {
+        let states = servers.iter()
+            .map(|server| (server.name.clone(), (server.addresses[0].url.clone(), ServerState::Disconnected)))
+            .collect();
+        ServerStatus { states: Mutex::new(states), scan: AtomicBool::new(scan) }
+    }
+
+    pub fn scan(&self) -> bool {
+        self.scan.load(Ordering::SeqCst)
+    }
+
+    /// Starts or stops scanning on every connected server
+    pub fn set_scan(&self, scan: bool, clients: &Clients, osc_send: &OscSender) {
+        self.scan.store(scan, Ordering::SeqCst);
+        for client in clients.lock().expect("unexpected").iter() {
+            let client = client.clone();
+            tokio::spawn(async move {
+                let result = if scan { client.start_scanning().await } else { client.stop_scanning().await };
+                result.map_err(|e| error!("{:?}", e))
+            });
+        }
+        let state = if scan { ServerState::Scanning } else { ServerState::Connected };
+        for (name, (address, previous)) in self.states.lock().expect("unexpected").iter_mut() {
+            if *previous != ServerState::Disconnected && *previous != state {
+                *previous = state;
+                info!("Intiface ({}) {}", address, state.as_str());
+                send_state(osc_send, name, address, state);
+            }
+        }
+    }
+
+    fn set(&self, server: &Server, address: &str, state: ServerState, osc_send: &OscSender) {
+        let mut states = self.states.lock().expect("unexpected");
+        let previous = states.insert(server.name.clone(), (String::from(address), state));
+        if previous.is_none_or(|previous| previous != (String::from(address), state)) {
+            info!("Intiface ({}) {}", address, state.as_str());
+            send_state(osc_send, &server.name, address, state);
+        }
+    }
+
+    /// Whether any server is connected
+    pub fn connected(&self) -> bool {
+        self.states.lock().expect("unexpected").values().any(|(_, state)| *state != ServerState::Disconnected)
+    }
+
+    /// (name, address, state) of each server
+    pub fn states(&self) -> Vec<(String, String, &'static str)> {
+        self.states.lock().expect("unexpected").iter()
+            .map(|(name, (address, state))| (name.clone(), address.clone(), state.as_str()))
+            .collect()
+    }
+
+    /// Sends the state of every server
+    pub fn report(&self, osc_send: &OscSender) {
+        for (name, (address, state)) in self.states.lock().expect("unexpected").iter() {
+            send_state(osc_send, name, address, *state);
+        }
+    }
+}
+
+fn send_state(osc_send: &OscSender, server_name: &str, address: &str, state: ServerState) {
+    osc_send.send(
+        &format!("/status/server/{}", server_name),
+        vec![OscType::String(String::from(state.as_str())), OscType::String(String::from(address))],
+    );
+}
+
+/// Keeps the connections to all the servers, merging their devices into one registry
+pub struct ConnectionManager {
+    pub devices: Mutex<evmap::WriteHandle<&'static str, Device>>,
+    pub device_filter: DeviceFilter,
+    pub default_patterns: Arc<DefaultPatterns>,
+    pub states: DeviceStates,
+    pub clients: Clients,
+    pub osc_send: Arc<OscSender>,
+    pub status: Arc<ServerStatus>,
+    /// Shown in the server's list of connections
+    pub client_name: String,
+    /// Devices are also registered as `<server>/<device>`
+    pub prefixed: bool,
+    pub insecure_tls: bool,
+    /// Interval of the pings which detect a dead connection
+    pub keepalive: Option<Duration>,
+    pub keepalive_timeout: Duration,
+    pub reconnect_max_interval: Duration,
+    pub reconnect_max_attempts: Option<u32>,
+}
+
+impl ConnectionManager {
+    /// Err once any server exceeded the reconnect attempts
+    pub async fn run(self: Arc<Self>, servers: Vec<Server>) -> Result<()> {
+        let connections = servers.into_iter()
+            .map(|server| tokio::spawn(self.clone().keep_connected(server)));
+        for result in futures_util::future::join_all(connections).await {
+            result??;
+        }
+        Ok(())
+    }
+
+    /// Tries the addresses in order, starting over from the primary whenever the connection is lost
+    async fn keep_connected(self: Arc<Self>, server: Server) -> Result<()> {
+        // consecutive rounds in which none of the addresses connected
+        let mut failures = 0;
+        loop {
+            let mut connected = false;
+            for index in 0..server.addresses.len() {
+                match tokio::spawn(self.clone().connect(server.clone(), index)).await {
+                    Ok(Ok(())) => {
+                        connected = true;
+                        break;
+                    }
+                    Ok(Err(e)) => warn!("Couldn't connect to Intiface ({}): {:?}", server.addresses[index].url, e),
+                    Err(e) => error!("{:?}", e),
+                }
+            }
+            if connected {
+                failures = 0;
+                METRICS.reconnect();
+                continue;
+            }
+            failures += 1;
+            if self.reconnect_max_attempts.is_some_and(|max_attempts| failures >= max_attempts) {
+                bail!("Couldn't connect to Intiface ({}) in {} attempts", server.name, failures);
+            }
+            let delay = reconnect_delay(failures, self.reconnect_max_interval);
+            info!("Reconnecting to Intiface ({}) in {:.1}s", server.name, delay.as_secs_f64());
+            tokio::time::sleep(delay).await;
+            METRICS.reconnect();
+        }
+    }
+
+    /// Err if couldn't connect, Ok once the established connection is lost
+    /// (or, connected to a backup, the primary is back)
+    async fn connect(self: Arc<Self>, server: Server, index: usize) -> Result<()> {
+        let address = server.addresses[index].url.clone();
+        info!("Starting Intiface Client ({})", address);
+        // https://buttplug-developer-guide.docs.buttplug.io/writing-buttplug-applications/device-enum.html#device-connection-events-and-storage
+        // > The server could already be running and have devices connected to it. In this case, the Client will emit DeviceAdded events on successful connection.
+        // > This means you will want to have your event handlers set up BEFORE connecting, in order to catch these messages.
+
+        let client = Arc::new(ButtplugClient::new(&self.client_name));
+        let mut event_stream = client.event_stream();
+        // removed from the routing map once the connection is lost
+        let connected = Mutex::new(Vec::new());
+        let event_loop = async {
+            while let Some(event) = event_stream.next().await {
+                match event {
+                    ButtplugClientEvent::DeviceAdded(device) => {
+                        let name = normalize_device_name(device.name());
+                        if !self.device_filter.accepts(&name) {
+                            info!("[{}] ignored", name);
+                            continue;
+                        }
+                        if self.simulated(&name) {
+                            warn!("[{}] ignored, the name is taken by a --simulate-device", name);
+                            continue;
+                        }
+                        let device = Device::new(device, Some(&server.name));
+                        self.add_device(&server, &name, &device);
+                        connected.lock().expect("unexpected").push(device.clone());
+                        info!("[{}] added", name);
+                        self.states.added(&name);
+                        self.osc_send.send("/events/device/added", vec![OscType::String(name)]);
+                        self.default_patterns.start(&device);
+                    }
+                    ButtplugClientEvent::DeviceRemoved(device) => {
+                        let name = normalize_device_name(device.name());
+                        if !self.device_filter.accepts(&name) {
+                            continue;
+                        }
+                        warn!("[{}] removed", name);
+                        let device = Device::from(device);
+                        remove_device(&mut self.devices.lock().expect("unexpected"), &device);
+                        connected.lock().expect("unexpected").retain(|d| *d != device);
+                        self.osc_send.send("/events/device/removed", vec![OscType::String(name)]);
+                        // rescanning, maybe a temporary disconnect
+                        if self.status.scan() {
+                            let _ = client.stop_scanning().await;
+                            let _ = client.start_scanning().await;
+                        }
+                    }
+                    ButtplugClientEvent::ServerDisconnect => {
+                        self.status.set(&server, &address, ServerState::Disconnected, &self.osc_send);
+                        self.osc_send.send("/events/server/disconnected", vec![OscType::String(address.clone())]);
+                        bail!("ServerDisconnect");
+                    }
+                    _ => {}
+                }
+            };
+            Ok::<(), Error>(())
+        };
+
+        client.connect(connector(&server.addresses[index], self.insecure_tls)).await?;
+        self.osc_send.send("/events/server/connected", vec![OscType::String(address.clone())]);
+        self.clients.lock().expect("unexpected").push(client.clone());
+        self.status.set(&server, &address, ServerState::Connected, &self.osc_send);
+        let keepalive = async {
+            let interval = match self.keepalive {
+                Some(interval) => interval,
+                None => return std::future::pending::<Result<()>>().await,
+            };
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                let start = std::time::Instant::now();
+                match tokio::time::timeout(self.keepalive_timeout, client.ping()).await {
+                    Ok(Ok(())) => {
+                        debug!("Intiface ({}) ping", address);
+                        METRICS.ping(start.elapsed());
+                    }
+                    Ok(Err(e)) => bail!("ping failed: {:?}", e),
+                    Err(_) => bail!("ping timed out"),
+                }
+            }
+        };
+        let primary_probe = async {
+            if index == 0 {
+                return std::future::pending::<Result<()>>().await;
+            }
+            let mut interval = tokio::time::interval(PRIMARY_PROBE_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let probe = ButtplugClient::new(&self.client_name);
+                if probe.connect(connector(&server.addresses[0], self.insecure_tls)).await.is_ok() {
+                    let _ = probe.disconnect().await;
+                    bail!("primary Intiface ({}) is back", server.addresses[0].url);
+                }
+            }
+        };
+        let result = async {
+            if self.status.scan() {
+                client.start_scanning().await?;
+                self.status.set(&server, &address, ServerState::Scanning, &self.osc_send);
+            }
+            tokio::select! {
+                result = event_loop => result,
+                result = keepalive => result,
+                result = primary_probe => result,
+            }
+        }.await;
+        if result.is_err() {
+            let _ = client.disconnect().await;
+        }
+        self.status.set(&server, &address, ServerState::Disconnected, &self.osc_send);
+        self.clients.lock().expect("unexpected").retain(|c| !Arc::ptr_eq(c, &client));
+        let mut devices = self.devices.lock().expect("unexpected");
+        for device in connected.into_inner().expect("unexpected") {
+            remove_device(&mut devices, &device);
+        }
+        if let Err(e) = result {
+            warn!("Intiface Client ({}) disconnected: {:?}", address, e);
+        }
+        Ok(())
+    }
+
+    /// A --simulate-device has the name, not to be replaced by a device of a server
+    fn simulated(&self, name: &str) -> bool {
+        self.devices.lock().expect("unexpected").get_one(name)
+            .is_some_and(|device| device.registration.server.is_none())
+    }
+
+    fn add_device(&self, server: &Server, name: &str, device: &Device) {
+        let mut devices = self.devices.lock().expect("unexpected");
+        let name = Box::leak(String::from(name).into_boxed_str());
+        devices.update(name, device.clone());
+        if self.prefixed {
+            let prefixed_name = Box::leak(format!("{}/{}", server.name, name).into_boxed_str());
+            devices.update(prefixed_name, device.clone());
+        }
+        devices.update(DEVICES_LAST, device.clone());
+        devices.refresh();
+    }
+}
+
+/// Exponential backoff from 1 second up to the max interval, randomly shortened by up to a half
+/// so that the clients of a restarted server don't reconnect all at once
+pub fn reconnect_delay(failures: u32, max_interval: Duration) -> Duration {
+    let backoff = Duration::from_secs(1)
+        .checked_mul(1 << failures.saturating_sub(1).min(16))
+        .map_or(max_interval, |backoff| backoff.min(max_interval));
+    backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+/// Removes the device from the routing map, except for the names taken over by another device since;
+/// `last` falls back to the newest remaining device
+fn remove_device(devices: &mut evmap::WriteHandle<&'static str, Device>, device: &Device) {
+    let (keys, last_removed, newest) = match devices.read() {
+        Some(map) => (
+            map.iter()
+                .filter(|(k, v)| **k != DEVICES_LAST && v.get_one() == Some(device))
+                .map(|(k, _)| *k)
+                .collect::<Vec<_>>(),
+            map.get_one(&DEVICES_LAST) == Some(device),
+            map.iter()
+                .filter(|(k, _)| **k != DEVICES_LAST)
+                .filter_map(|(_, v)| v.get_one().cloned())
+                .filter(|d| d != device)
+                .max_by_key(|d| d.registration.sequence),
+        ),
+        None => (Vec::new(), false, None),
+    };
+    for key in keys {
+        devices.empty(key);
+    }
+    if last_removed {
+        match newest {
+            Some(device) => { devices.update(DEVICES_LAST, device); }
+            None => { devices.empty(DEVICES_LAST); }
+        }
+    }
+    devices.refresh();
+}
@@ -0,0 +1,120 @@
+use std::io::Write;
+use std::path::Path;
+use buttplug::core::message::ActuatorType;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use rand::Rng;
+use crate::{Device, normalize_device_name};
+
+/// Width of a fader column, in TouchOSC units
+const COLUMN: u32 = 100;
+const HEIGHT: u32 = 720;
+
+/// Controls of a device: its name and the number of vibrators
+pub struct LayoutDevice {
+    name: String,
+    vibrators: usize,
+}
+
+impl LayoutDevice {
+    pub fn new(device: &Device) -> Self {
+        let vibrators = device.message_attributes().scalar_cmd().iter().flatten()
+            .filter(|attribute| *attribute.actuator_type() == ActuatorType::Vibrate)
+            .count();
+        LayoutDevice { name: normalize_device_name(device.name()), vibrators }
+    }
+}
+
+/// Writes the layout for TouchOSC (https://hexler.net/touchosc): per device, a vibration fader,
+/// one more per motor if there are several, and a stop button
+pub fn touchosc(path: &Path, devices: &[LayoutDevice], namespace: &str) -> std::io::Result<()> {
+    let mut children = String::new();
+    let mut x = 0;
+    for device in devices {
+        let prefix = format!("{}/devices/{}", namespace, device.name);
+        let motors = if device.vibrators > 1 { device.vibrators } else { 0 };
+        let width = COLUMN * (1 + motors as u32);
+        children += &label(&device.name, (x, 0, width, 40));
+        children += &fader(&format!("{} vibrate", device.name), &format!("{}/vibrate/speed", prefix), (x, 40, COLUMN, 560));
+        for motor in 0..motors {
+            children += &fader(
+                &format!("{} motor {}", device.name, motor),
+                &format!("{}/scalar/vibrate/{}", prefix, motor),
+                (x + COLUMN * (1 + motor as u32), 40, COLUMN, 560),
+            );
+        }
+        children += &button(&format!("{} stop", device.name), &format!("{}/stop", prefix), (x, 620, width, 100));
+        x += width;
+    }
+    let xml = format!(
+        "<?xml version='1.0' encoding='UTF-8'?><lexml version='3'><node ID='{}' type='GROUP'><properties>{}{}</properties>\
+        <values></values><messages></messages><children>{}</children></node></lexml>",
+        id(), property("s", "name", "buttplug-osc"), frame((0, 0, x.max(COLUMN), HEIGHT)), children,
+    );
+    // .tosc is the zlib-compressed XML
+    let mut encoder = ZlibEncoder::new(std::fs::File::create(path)?, Compression::default());
+    encoder.write_all(xml.as_bytes())?;
+    encoder.finish()?;
+    Ok(())
+}
+
+fn label(text: &str, rect: (u32, u32, u32, u32)) -> String {
+    format!(
+        "<node ID='{}' type='LABEL'><properties>{}{}{}</properties><values></values><messages></messages></node>",
+        id(), property("s", "name", text), property("s", "text", text), frame(rect),
+    )
+}
+
+/// Sends its value from 0 to 1 whenever it changes
+fn fader(name: &str, address: &str, rect: (u32, u32, u32, u32)) -> String {
+    format!(
+        "<node ID='{}' type='FADER'><properties>{}{}</properties><values>{}</values><messages>{}</messages></node>",
+        id(), property("s", "name", name), frame(rect), value(), osc(address, "ANY", true),
+    )
+}
+
+/// Sends the message without arguments when pressed
+fn button(name: &str, address: &str, rect: (u32, u32, u32, u32)) -> String {
+    format!(
+        "<node ID='{}' type='BUTTON'><properties>{}{}</properties><values>{}</values><messages>{}</messages></node>",
+        id(), property("s", "name", name), frame(rect), value(), osc(address, "RISE", false),
+    )
+}
+
+fn property(property_type: &str, key: &str, value: &str) -> String {
+    format!("<property type='{}'><key><![CDATA[{}]]></key><value><![CDATA[{}]]></value></property>", property_type, key, value)
+}
+
+fn frame((x, y, w, h): (u32, u32, u32, u32)) -> String {
+    format!("<property type='r'><key><![CDATA[frame]]></key><value><x>{}</x><y>{}</y><w>{}</w><h>{}</h></value></property>", x, y, w, h)
+}
+
+fn value() -> String {
+    String::from(
+        "<value><key><![CDATA[x]]></key><locked>0</locked><lockedDefaultCurrent>0</lockedDefaultCurrent>\
+        <default><![CDATA[0]]></default><defaultPull>0</defaultPull></value>",
+    )
+}
+
+fn osc(address: &str, condition: &str, with_value: bool) -> String {
+    let arguments = if with_value {
+        "<partial><type>VALUE</type><conversion>FLOAT</conversion><value><![CDATA[x]]></value>\
+        <scaleMin>0</scaleMin><scaleMax>1</scaleMax></partial>"
+    } else {
+        ""
+    };
+    format!(
+        "<osc><enabled>1</enabled><send>1</send><receive>0</receive><feedback>0</feedback><connections>00001</connections>\
+        <triggers><trigger><var><![CDATA[x]]></var><condition>{}</condition></trigger></triggers>\
+        <path><partial><type>CONSTANT</type><conversion>STRING</conversion><value><![CDATA[{}]]></value>\
+        <scaleMin>0</scaleMin><scaleMax>1</scaleMax></partial></path><arguments>{}</arguments></osc>",
+        condition, address, arguments,
+    )
+}
+
+/// Random UUID of a node
+fn id() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
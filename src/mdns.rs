@@ -0,0 +1,24 @@
+use tracing::info;
+
+/// Keeps the mDNS services registered until dropped
+pub struct Advertisement {
+    _responder: libmdns::Responder,
+    _services: Vec<libmdns::Service>,
+}
+
+/// `instance_name` is the --client-name
+pub fn advertise(instance_name: &str, osc_port: u16, oscquery_port: Option<u16>) -> Advertisement {
+    let responder = libmdns::Responder::new().expect("Invalid --mdns: couldn't start responder");
+    let mut services = Vec::new();
+    info!("Advertising _osc._udp (port {}) via mDNS", osc_port);
+    services.push(responder.register(
+        String::from("_osc._udp"), String::from(instance_name), osc_port, &["txtvers=1"],
+    ));
+    if let Some(oscquery_port) = oscquery_port {
+        info!("Advertising _oscjson._tcp (port {}) via mDNS", oscquery_port);
+        services.push(responder.register(
+            String::from("_oscjson._tcp"), String::from(instance_name), oscquery_port, &["txtvers=1"],
+        ));
+    }
+    Advertisement { _responder: responder, _services: services }
+}
@@ -0,0 +1,261 @@
+// https://prometheus.io/docs/instrumenting/exposition_formats/
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use crate::intiface::ServerStatus;
+
+/// Upper bounds of the dispatch latency histogram buckets, in seconds
+const DISPATCH_BUCKETS: [f64; 8] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.1];
+/// Upper bounds of the command latency histogram buckets, in seconds: Intiface and Bluetooth are much slower
+const COMMAND_BUCKETS: [f64; 8] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+struct Histogram {
+    /// Upper bounds of the buckets, in seconds
+    bounds: [f64; 8],
+    buckets: [AtomicU64; 8],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    const fn new(bounds: [f64; 8]) -> Self {
+        Histogram { bounds, buckets: [ZERO; 8], count: ZERO, sum_nanos: ZERO }
+    }
+
+    fn observe(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        for (bucket, le) in self.buckets.iter().zip(self.bounds.iter()) {
+            if seconds <= *le {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// In seconds, 0 if nothing was observed
+    fn mean(&self) -> f64 {
+        match self.count.load(Ordering::Relaxed) {
+            0 => 0.0,
+            count => self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9 / count as f64,
+        }
+    }
+
+    fn render(&self, text: &mut String, name: &str, help: &str) {
+        let _ = writeln!(text, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+        for (bucket, le) in self.buckets.iter().zip(self.bounds.iter()) {
+            let _ = writeln!(text, "{}_bucket{{le=\"{}\"}} {}", name, le, bucket.load(Ordering::Relaxed));
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(text, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(text, "{}_sum {}", name, self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9);
+        let _ = writeln!(text, "{}_count {}", name, count);
+    }
+}
+
+pub static METRICS: Metrics = Metrics::new();
+
+/// Counted regardless of --metrics-listen
+pub struct Metrics {
+    received: AtomicU64,
+    invalid: AtomicU64,
+    rate_limited: AtomicU64,
+    reconnects: AtomicU64,
+    /// By normalized device name
+    commands: Mutex<BTreeMap<String, u64>>,
+    errors: Mutex<BTreeMap<String, u64>>,
+    dispatch: Histogram,
+    command: Histogram,
+    /// Round trip of the latest --keepalive ping, in nanoseconds
+    ping_nanos: AtomicU64,
+    /// Sender of the latest OSC packet, for --tui
+    last_source: Mutex<Option<String>>,
+    /// Of the latest OSC message, in milliseconds since the Unix epoch, 0 if none yet
+    last_received_millis: AtomicU64,
+    /// Of the latest command by normalized device name, in milliseconds since the Unix epoch
+    last_commands: Mutex<BTreeMap<String, u64>>,
+    /// Whether each receiver is up, e.g. OSC => false if its socket couldn't be bound
+    listeners: Mutex<BTreeMap<&'static str, bool>>,
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            received: ZERO,
+            invalid: ZERO,
+            rate_limited: ZERO,
+            reconnects: ZERO,
+            commands: Mutex::new(BTreeMap::new()),
+            errors: Mutex::new(BTreeMap::new()),
+            dispatch: Histogram::new(DISPATCH_BUCKETS),
+            command: Histogram::new(COMMAND_BUCKETS),
+            ping_nanos: ZERO,
+            last_source: Mutex::new(None),
+            last_received_millis: ZERO,
+            last_commands: Mutex::new(BTreeMap::new()),
+            listeners: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.last_received_millis.store(unix_millis(), Ordering::Relaxed);
+    }
+
+    pub fn received_total(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    pub fn source(&self, source: &str) {
+        let mut last_source = self.last_source.lock().expect("unexpected");
+        if last_source.as_deref() != Some(source) {
+            *last_source = Some(String::from(source));
+        }
+    }
+
+    pub fn last_source(&self) -> Option<String> {
+        self.last_source.lock().expect("unexpected").clone()
+    }
+
+    pub fn commands(&self, device_name: &str) -> (u64, u64) {
+        let commands = self.commands.lock().expect("unexpected").get(device_name).copied().unwrap_or_default();
+        let errors = self.errors.lock().expect("unexpected").get(device_name).copied().unwrap_or_default();
+        (commands, errors)
+    }
+
+    pub fn invalid(&self) {
+        self.invalid.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Messages dropped over --osc-rate-limit
+    pub fn rate_limited(&self, count: u64) {
+        self.rate_limited.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Registers the receiver as down before starting it, then as up once it's bound
+    pub fn listener(&self, name: &'static str, up: bool) {
+        self.listeners.lock().expect("unexpected").insert(name, up);
+    }
+
+    /// Counts the command, and its error and latency once the device replies
+    pub fn command(&'static self, device_name: &str, sent: JoinHandle<Result<(), ()>>) {
+        *self.commands.lock().expect("unexpected").entry(String::from(device_name)).or_default() += 1;
+        self.last_commands.lock().expect("unexpected").insert(String::from(device_name), unix_millis());
+        let device_name = String::from(device_name);
+        let start = Instant::now();
+        tokio::spawn(async move {
+            match sent.await {
+                Ok(Ok(())) => self.command.observe(start.elapsed()),
+                Ok(Err(())) => *self.errors.lock().expect("unexpected").entry(device_name).or_default() += 1,
+                Err(_) => {}
+            }
+        });
+    }
+
+    /// From receiving an OSC message to sending the commands to the devices
+    pub fn dispatched(&self, latency: Duration) {
+        self.dispatch.observe(latency);
+    }
+
+    pub fn ping(&self, latency: Duration) {
+        self.ping_nanos.store(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Mean dispatch and command latencies, and the latest ping, in milliseconds
+    pub fn latency(&self) -> (f64, f64, f64) {
+        (self.dispatch.mean() * 1e3, self.command.mean() * 1e3, self.ping_nanos.load(Ordering::Relaxed) as f64 / 1e6)
+    }
+
+    fn render(&self) -> String {
+        let mut text = String::new();
+        let counter = |text: &mut String, name: &str, help: &str, value: &AtomicU64| {
+            let _ = writeln!(text, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value.load(Ordering::Relaxed));
+        };
+        counter(&mut text, "buttplug_osc_messages_received_total", "OSC messages received", &self.received);
+        counter(&mut text, "buttplug_osc_messages_invalid_total", "OSC messages rejected as invalid", &self.invalid);
+        counter(&mut text, "buttplug_osc_messages_rate_limited_total", "OSC messages dropped over --osc-rate-limit", &self.rate_limited);
+        counter(&mut text, "buttplug_osc_reconnects_total", "Attempts to reconnect to Intiface", &self.reconnects);
+        for (name, help, values) in [
+            ("buttplug_osc_commands_total", "Commands sent to the device", &self.commands),
+            ("buttplug_osc_command_errors_total", "Commands the device failed", &self.errors),
+        ].iter() {
+            let _ = writeln!(text, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+            for (device, value) in values.lock().expect("unexpected").iter() {
+                let _ = writeln!(text, "{}{{device=\"{}\"}} {}", name, device, value);
+            }
+        }
+        self.dispatch.render(&mut text, "buttplug_osc_dispatch_seconds", "Latency from receiving an OSC message to sending the commands");
+        self.command.render(&mut text, "buttplug_osc_command_seconds", "Latency from sending a command to the device replying, via Intiface");
+        let name = "buttplug_osc_ping_seconds";
+        let _ = writeln!(
+            text, "# HELP {} Round trip of the latest ping to Intiface (--keepalive)\n# TYPE {} gauge\n{} {}",
+            name, name, name, self.ping_nanos.load(Ordering::Relaxed) as f64 / 1e9,
+        );
+        text
+    }
+
+    /// 200 if every receiver is up and any Intiface server is connected, 503 otherwise;
+    /// the timestamps are in seconds since the Unix epoch
+    fn health(&self, server_status: &ServerStatus) -> (u16, String) {
+        let seconds = |millis: u64| millis as f64 / 1e3;
+        let listeners = self.listeners.lock().expect("unexpected").clone();
+        let healthy = listeners.values().all(|up| *up) && server_status.connected();
+        let servers: Vec<_> = server_status.states().into_iter()
+            .map(|(name, address, state)| serde_json::json!({"name": name, "address": address, "state": state}))
+            .collect();
+        let last_commands: BTreeMap<_, _> = self.last_commands.lock().expect("unexpected").iter()
+            .map(|(device, millis)| (device.clone(), seconds(*millis)))
+            .collect();
+        let last_received = match self.last_received_millis.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(seconds(millis)),
+        };
+        let status = if healthy { "ok" } else { "unhealthy" };
+        let body = serde_json::json!({
+            "status": status,
+            "listeners": listeners,
+            "servers": servers,
+            "last_received": last_received,
+            "last_commands": last_commands,
+        });
+        (if healthy { 200 } else { 503 }, body.to_string())
+    }
+}
+
+/// Serves /metrics, and /healthz for the container orchestration and uptime monitors
+pub fn metrics_listen(host_port: &str, server_status: Arc<ServerStatus>) {
+    let server = tiny_http::Server::http(host_port).expect("Invalid --metrics-listen: couldn't bind socket");
+    let content_type = tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..])
+        .expect("unexpected");
+    let json = tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("unexpected");
+    for request in server.incoming_requests() {
+        debug!("[Metrics] {} {}", request.method(), request.url());
+        let result = if request.url() == "/metrics" {
+            request.respond(tiny_http::Response::from_string(METRICS.render()).with_header(content_type.clone()))
+        } else if request.url() == "/healthz" {
+            let (status, body) = METRICS.health(&server_status);
+            request.respond(tiny_http::Response::from_string(body).with_status_code(status).with_header(json.clone()))
+        } else {
+            request.respond(tiny_http::Response::empty(404))
+        };
+        if let Err(e) = result {
+            warn!("[Metrics] {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+use midir::{MidiInput, MidiInputConnection};
+use tracing::{debug, info};
+use crate::OscHandler;
+
+/// Addresses of the received MIDI messages, routed by `[[route]]`
+pub const PREFIX: &str = "/midi/";
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const CONTROL_CHANGE: u8 = 0xB0;
+
+/// Receives MIDI from the port (index or part of the name) as OSC messages:
+/// `/midi/<channel>/cc/<controller> <value>` and `/midi/<channel>/note/<note> <velocity>`, zero on note-off;
+/// channels are 1..16, values 0..127
+pub fn midi_listen(port: &str, client_name: &str, osc_handler: OscHandler) -> MidiInputConnection<()> {
+    let input = MidiInput::new(client_name).expect("Invalid --midi-in: couldn't initialize MIDI");
+    let ports = input.ports();
+    let names: Vec<String> = ports.iter().map(|port| input.port_name(port).unwrap_or_default()).collect();
+    let index = port.parse::<usize>().ok()
+        .filter(|index| *index < ports.len())
+        .or_else(|| names.iter().position(|name| name.contains(port)))
+        .unwrap_or_else(|| panic!("Invalid --midi-in: no such port, available: {:?}", names));
+    info!("Starting MIDI Receiver ({})", names[index]);
+    // the callback runs on the MIDI thread, the commands are dispatched as tasks
+    let runtime = tokio::runtime::Handle::current();
+    input.connect(&ports[index], client_name, move |_, data, _| {
+        let _runtime = runtime.enter();
+        if let Some(message) = osc_message(data) {
+            debug!("[MIDI] {:?}", data);
+            osc_handler.handle_packet(osc::Packet::Message(message));
+        }
+    }, ()).unwrap_or_else(|e| panic!("Invalid --midi-in: {}", e))
+}
+
+fn osc_message(data: &[u8]) -> Option<osc::Message> {
+    let (status, channel) = (data.first()? & 0xF0, (data.first()? & 0x0F) + 1);
+    let (kind, number, value) = match (status, data.get(1), data.get(2)) {
+        (CONTROL_CHANGE, Some(controller), Some(value)) => ("cc", controller, *value),
+        (NOTE_ON, Some(note), Some(velocity)) => ("note", note, *velocity),
+        (NOTE_OFF, Some(note), Some(_)) => ("note", note, 0),
+        _ => return None,
+    };
+    Some(osc::Message {
+        addr: format!("{}{}/{}/{}", PREFIX, channel, kind, number),
+        args: Some(vec![OscType::Int(value.into())]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(addr: &str, value: i32) -> Option<osc::Message> {
+        Some(osc::Message { addr: String::from(addr), args: Some(vec![OscType::Int(value)]) })
+    }
+
+    #[test]
+    fn control_change() {
+        assert_eq!(osc_message(&[CONTROL_CHANGE | 1, 7, 100]), message("/midi/2/cc/7", 100));
+    }
+
+    #[test]
+    fn notes() {
+        assert_eq!(osc_message(&[NOTE_ON, 60, 127]), message("/midi/1/note/60", 127));
+        assert_eq!(osc_message(&[NOTE_OFF | 15, 60, 64]), message("/midi/16/note/60", 0));
+    }
+
+    #[test]
+    fn other_messages_are_ignored() {
+        // pitch bend
+        assert_eq!(osc_message(&[0xE0, 0, 64]), None);
+        assert_eq!(osc_message(&[NOTE_ON, 60]), None);
+        assert_eq!(osc_message(&[]), None);
+    }
+}
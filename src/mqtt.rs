@@ -0,0 +1,170 @@
+use std::time::Duration;
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use url::Url;
+use tracing::{debug, info, warn};
+use crate::OscHandler;
+
+/// Subtopic of the published messages, e.g. `<prefix>/out/events/device/added`
+const OUT: &str = "out";
+/// https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Publishes the messages sent via OscSender
+#[derive(Clone)]
+pub struct MqttPublisher {
+    client: AsyncClient,
+    prefix: String,
+    /// Publishing Home Assistant discovery of the added devices
+    discovery: bool,
+}
+
+impl MqttPublisher {
+    pub fn publish(&self, addr: &str, args: &[OscType]) {
+        let topic = format!("{}/{}{}", self.prefix, OUT, addr);
+        if let Err(e) = self.client.try_publish(&topic, QoS::AtMostOnce, false, payload(args)) {
+            warn!("[MQTT] {}: {:?}", topic, e);
+        }
+        if !self.discovery {
+            return;
+        }
+        match (addr, args.first()) {
+            ("/events/device/added", Some(OscType::String(name))) => {
+                self.discover(name);
+                self.retain(&self.availability_topic(name), String::from("online"));
+            }
+            ("/events/device/removed", Some(OscType::String(name))) => {
+                self.retain(&self.availability_topic(name), String::from("offline"));
+            }
+            _ => {}
+        }
+    }
+
+    /// Home Assistant entities of the device: vibration speed slider and stop button, commanded via `<prefix>/<device>/...`
+    fn discover(&self, name: &str) {
+        let id = object_id(&format!("{}_{}", self.prefix, name));
+        let device = serde_json::json!({
+            "identifiers": [id],
+            "name": name,
+            "manufacturer": "buttplug.io",
+        });
+        let vibrate = serde_json::json!({
+            "name": "Vibration",
+            "unique_id": format!("{}_vibrate", id),
+            "command_topic": format!("{}/{}/vibrate", self.prefix, name),
+            "min": 0.0,
+            "max": 1.0,
+            "step": 0.01,
+            "mode": "slider",
+            "availability_topic": self.availability_topic(name),
+            "device": device,
+        });
+        let stop = serde_json::json!({
+            "name": "Stop",
+            "unique_id": format!("{}_stop", id),
+            "command_topic": format!("{}/{}/stop", self.prefix, name),
+            "payload_press": "",
+            "availability_topic": self.availability_topic(name),
+            "device": device,
+        });
+        self.retain(&format!("{}/number/{}/vibrate/config", DISCOVERY_PREFIX, id), vibrate.to_string());
+        self.retain(&format!("{}/button/{}/stop/config", DISCOVERY_PREFIX, id), stop.to_string());
+    }
+
+    fn availability_topic(&self, name: &str) -> String {
+        format!("{}/{}/availability/{}", self.prefix, OUT, name)
+    }
+
+    fn retain(&self, topic: &str, payload: String) {
+        if let Err(e) = self.client.try_publish(topic, QoS::AtLeastOnce, true, payload) {
+            warn!("[MQTT] {}: {:?}", topic, e);
+        }
+    }
+}
+
+/// mqtt://[<user>:<password>@]<host>[:<port>]
+pub fn connect(url: &Url, client_name: &str, prefix: &str, discovery: bool) -> (MqttPublisher, EventLoop) {
+    if url.scheme() != "mqtt" {
+        unimplemented!("Invalid --mqtt-connect: only mqtt:// is supported currently");
+    }
+    let host = url.host_str().expect("Invalid --mqtt-connect");
+    let mut options = MqttOptions::new(client_name, host, url.port().unwrap_or(1883));
+    options.set_keep_alive(Duration::from_secs(30));
+    if !url.username().is_empty() {
+        options.set_credentials(url.username(), url.password().unwrap_or_default());
+    }
+    let (client, event_loop) = AsyncClient::new(options, 64);
+    (MqttPublisher { client, prefix: String::from(prefix.trim_end_matches('/')), discovery }, event_loop)
+}
+
+/// Receives `<prefix>/<device>/<command>` (and `<prefix>/stop`) as `/devices/<device>/<command>` (and `/stop`),
+/// `vibrate` and `rotate` being short for `vibrate/speed` and `rotate/speed`;
+/// the payload is the arguments separated by spaces, numbers are floats
+pub async fn mqtt_listen(publisher: MqttPublisher, mut event_loop: EventLoop, osc_handler: OscHandler) {
+    let out = format!("{}/{}/", publisher.prefix, OUT);
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("[MQTT] connected");
+                // subscribing again after a reconnection
+                if let Err(e) = publisher.client.subscribe(format!("{}/#", publisher.prefix), QoS::AtMostOnce).await {
+                    warn!("[MQTT] {:?}", e);
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                if publish.topic.starts_with(&out) {
+                    continue;
+                }
+                let path = match publish.topic.strip_prefix(&publisher.prefix) {
+                    Some(path) => path,
+                    None => continue,
+                };
+                let addr = match path {
+                    "/stop" => String::from(path),
+                    _ if path.ends_with("/vibrate") || path.ends_with("/rotate") => format!("/devices{}/speed", path),
+                    _ => format!("/devices{}", path),
+                };
+                let payload = String::from_utf8_lossy(&publish.payload);
+                debug!("[MQTT] {} {}", publish.topic, payload);
+                let addr = format!("{}{}", osc_handler.namespace, addr);
+                osc_handler.handle_packet(osc::Packet::Message(osc::Message { addr, args: Some(args(&payload)) }));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("[MQTT] {:?}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Only `[a-zA-Z0-9_-]` is allowed in the discovery topics
+fn object_id(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+fn args(payload: &str) -> Vec<OscType> {
+    payload.split_whitespace()
+        .map(|arg| match arg {
+            "true" => OscType::Bool(true),
+            "false" => OscType::Bool(false),
+            _ => arg.parse().map(OscType::Double).unwrap_or_else(|_| OscType::String(String::from(arg))),
+        })
+        .collect()
+}
+
+fn payload(args: &[OscType]) -> String {
+    args.iter()
+        .map(|arg| match arg {
+            OscType::Int(x) => x.to_string(),
+            OscType::Long(x) => x.to_string(),
+            OscType::Float(x) => x.to_string(),
+            OscType::Double(x) => x.to_string(),
+            OscType::Bool(x) => x.to_string(),
+            OscType::String(x) => x.clone(),
+            x => format!("{:?}", x),
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
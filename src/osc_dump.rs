@@ -0,0 +1,42 @@
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+
+/// Prints the received message regardless of the log level, as
+/// `<source> <address> ,<types> <values> -> <match result>`
+pub fn print(message: &osc::Message, source: Option<&str>, result: &str) {
+    let args = message.args.as_deref().unwrap_or_default();
+    let types: String = args.iter().map(type_tag).collect();
+    let mut line = format!("{} {} ,{}", source.unwrap_or("-"), message.addr, types);
+    for arg in args.iter() {
+        line.push(' ');
+        line.push_str(&value(arg));
+    }
+    eprintln!("{} -> {}", line, result);
+}
+
+fn type_tag(arg: &OscType) -> char {
+    match arg {
+        OscType::Int(_) => 'i',
+        OscType::Float(_) => 'f',
+        OscType::String(_) => 's',
+        OscType::Blob(_) => 'b',
+        OscType::Long(_) => 'h',
+        OscType::Double(_) => 'd',
+        OscType::Bool(true) => 'T',
+        OscType::Bool(false) => 'F',
+        _ => '?',
+    }
+}
+
+fn value(arg: &OscType) -> String {
+    match arg {
+        OscType::Int(value) => value.to_string(),
+        OscType::Float(value) => value.to_string(),
+        OscType::String(value) => format!("{:?}", value),
+        OscType::Blob(value) => format!("<{} bytes>", value.len()),
+        OscType::Long(value) => value.to_string(),
+        OscType::Double(value) => value.to_string(),
+        OscType::Bool(value) => value.to_string(),
+        other => format!("{:?}", other),
+    }
+}
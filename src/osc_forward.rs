@@ -0,0 +1,26 @@
+use nannou_osc as osc;
+use tracing::{debug, error};
+
+/// Re-sends the OSC packets received by --osc-listen to the --osc-forward destination
+pub struct OscForwarder {
+    sender: osc::Sender<osc::Connected>,
+    /// Only the messages neither routed nor accepted as commands, instead of every packet
+    pub unmatched: bool,
+}
+
+impl OscForwarder {
+    pub fn new(host_port: &str, unmatched: bool) -> Self {
+        let sender = osc::sender()
+            .expect("Invalid --osc-forward: couldn't bind socket")
+            .connect(host_port)
+            .expect("Invalid --osc-forward: couldn't connect socket");
+        OscForwarder { sender, unmatched }
+    }
+
+    pub fn forward(&self, packet: osc::Packet) {
+        debug!("[OSC forward] {:?}", packet);
+        if let Err(e) = self.sender.send(packet) {
+            error!("[OSC forward] {:?}", e);
+        }
+    }
+}
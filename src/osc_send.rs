@@ -0,0 +1,48 @@
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+use tokio::sync::broadcast;
+use tracing::{debug, error};
+use crate::mqtt::MqttPublisher;
+
+/// Sends OSC messages to the `--osc-send` destination (and publishes them via `--mqtt-connect`);
+/// does nothing when neither is configured
+pub struct OscSender {
+    sender: Option<osc::Sender<osc::Connected>>,
+    namespace: String,
+    mqtt: Option<MqttPublisher>,
+    /// Every sent message without the namespace, e.g. for the WebSocket clients
+    events: broadcast::Sender<(String, Vec<OscType>)>,
+}
+
+impl OscSender {
+    pub fn new(host_port: Option<&str>, namespace: &str, mqtt: Option<MqttPublisher>) -> Self {
+        let sender = host_port.map(|host_port| {
+            osc::sender()
+                .expect("Invalid --osc-send: couldn't bind socket")
+                .connect(host_port)
+                .expect("Invalid --osc-send: couldn't connect socket")
+        });
+        let (events, _) = broadcast::channel(64);
+        OscSender { sender, namespace: String::from(namespace), mqtt, events }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<(String, Vec<OscType>)> {
+        self.events.subscribe()
+    }
+
+    pub fn send(&self, addr: &str, args: Vec<OscType>) {
+        if let Some(ref mqtt) = self.mqtt {
+            mqtt.publish(addr, &args);
+        }
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send((String::from(addr), args.clone()));
+        }
+        if let Some(ref sender) = self.sender {
+            let addr = format!("{}{}", self.namespace, addr);
+            debug!("[{}] sending {:?}", addr, args);
+            if let Err(e) = sender.send((addr.as_str(), args)) {
+                error!("[{}] {:?}", addr, e);
+            }
+        }
+    }
+}
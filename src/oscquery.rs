@@ -0,0 +1,388 @@
+// https://github.com/Vidvox/OSCQueryProposal
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use buttplug::core::message::{ActuatorType, SensorType};
+use nannou_osc::rosc::OscType;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+use crate::{Device, DEVICES_ALL, DEVICES_FIRST, DEVICES_LAST, DEVICES_NEWEST, SCALAR_ACTUATORS};
+use crate::config::Mappings;
+use crate::sensor::SENSOR_TYPES;
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct Node {
+    full_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'static str>,
+    access: u8,
+    #[serde(rename = "TYPE", skip_serializing_if = "Option::is_none")]
+    osc_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    range: Option<Vec<Range>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contents: Option<BTreeMap<String, Node>>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct Range {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vals: Option<&'static [&'static str]>,
+}
+
+const ACCESS_NONE: u8 = 0;
+const ACCESS_WRITE: u8 = 2;
+
+const RANGE_UNIT: Range = Range { min: Some(0.0), max: Some(1.0), vals: None };
+const RANGE_SIGNED_UNIT: Range = Range { min: Some(-1.0), max: Some(1.0), vals: None };
+const RANGE_FREQUENCY: Range = Range { min: Some(0.0), max: Some(10.0), vals: None };
+const RANGE_WAVEFORM: Range = Range { min: None, max: None, vals: Some(&["sine", "triangle", "square"]) };
+const RANGE_ANY: Range = Range { min: None, max: None, vals: None };
+const RANGE_MOTOR: Range = Range { min: Some(0.0), max: Some(255.0), vals: None };
+const RANGE_MILLIS: Range = Range { min: Some(0.0), max: Some(60000.0), vals: None };
+
+/// Command to `/devices/<set>/<path>`; `validate_osc_message` accepts only these, and OSCQuery describes them.
+/// `{actuator}` and `{sensor}` are the types of the scalar actuators and the sensors, `{index}` is among those of the type
+pub struct DeviceCommand {
+    path: &'static str,
+    description: &'static str,
+    osc_type: Option<&'static str>,
+    range: &'static [Range],
+}
+
+const fn command(path: &'static str, description: &'static str, osc_type: Option<&'static str>, range: &'static [Range]) -> DeviceCommand {
+    DeviceCommand { path, description, osc_type, range }
+}
+
+pub const DEVICE_COMMANDS: &[DeviceCommand] = &[
+    command("stop", "Stop all actuators", None, &[]),
+    command("battery", "Reply with battery level via --osc-send", None, &[]),
+    command("features", "Reply with feature and step counts of each actuator type via --osc-send", None, &[]),
+    command("sensor/{sensor}/{index}/subscribe", "Send the readings of the sensor via --osc-send", None, &[]),
+    command("sensor/{sensor}/{index}/unsubscribe", "Stop sending the readings of the sensor", None, &[]),
+    command("vibrate/speed", "Vibration speed", Some("f"), &[RANGE_UNIT]),
+    command("vibrate/speeds", "Vibration speed of each motor, in order", Some("f"), &[RANGE_UNIT]),
+    command("vibrate/adjust", "Add to the current vibration speed", Some("f"), &[RANGE_SIGNED_UNIT]),
+    command("vibrate/hold", "Vibration speed until release", Some("f"), &[RANGE_UNIT]),
+    command("vibrate/release", "Restore the vibration speed from before hold", None, &[]),
+    command("vibrate/toggle", "Toggle vibration between zero and --toggle-speed", None, &[]),
+    command(
+        "vibrate/pulse", "Vibration speed for duration in milliseconds, then back to the previous one",
+        Some("fi"), &[RANGE_UNIT, RANGE_MILLIS],
+    ),
+    command(
+        "vibrate/pwm", "Toggle between vibration speed and zero at frequency (Hz) with duty cycle",
+        Some("fff"), &[RANGE_UNIT, RANGE_FREQUENCY, RANGE_UNIT],
+    ),
+    command(
+        "vibrate/ramp", "Change vibration speed gradually to the target over duration in milliseconds",
+        Some("fi"), &[RANGE_UNIT, RANGE_MILLIS],
+    ),
+    command("rotate/speed", "Rotation speed, negative is counterclockwise", Some("f"), &[RANGE_SIGNED_UNIT]),
+    command("oscillate/speed", "Oscillation speed", Some("f"), &[RANGE_UNIT]),
+    command("constrict/level", "Constriction level, e.g. of an air pump", Some("f"), &[RANGE_UNIT]),
+    command(
+        "pattern", "Play the named pattern: wave, heartbeat, escalate, random or user-defined", Some("s"), &[],
+    ),
+    command("pattern/stop", "Stop the pattern", None, &[]),
+    command(
+        "funscript/play", "Play the .funscript file (name in --funscript-dir) from offset in milliseconds",
+        Some("si"), &[RANGE_ANY, RANGE_ANY],
+    ),
+    command("funscript/stop", "Stop the funscript playback", None, &[]),
+    command(
+        "lfo", "Modulate vibration speed: sine|triangle|square, frequency (Hz), depth, offset",
+        Some("sfff"), &[RANGE_WAVEFORM, RANGE_FREQUENCY, RANGE_UNIT, RANGE_UNIT],
+    ),
+    command(
+        "rotateMap/speedMap", "Rotation speed of a single motor, negative is counterclockwise",
+        Some("if"), &[RANGE_MOTOR, RANGE_SIGNED_UNIT],
+    ),
+    command(
+        "scalar/{actuator}/{index}", "Level of a single actuator of the type, negative rotate is counterclockwise",
+        Some("f"), &[RANGE_SIGNED_UNIT],
+    ),
+    command(
+        "raw/write", "Write the bytes to the endpoint, optionally with response (--allow-raw)",
+        Some("sb"), &[RANGE_ANY, RANGE_ANY],
+    ),
+    command(
+        "sequence", "Vibration speeds at offsets in milliseconds: offset, speed, offset, speed...",
+        Some("if"), &[RANGE_MILLIS, RANGE_UNIT],
+    ),
+    command(
+        "stroke", "Move back and forth between positions at speed until the next command",
+        Some("fff"), &[RANGE_UNIT, RANGE_UNIT, RANGE_UNIT],
+    ),
+    command("linear", "Move to position over duration in milliseconds", Some("fi"), &[RANGE_UNIT, RANGE_MILLIS]),
+];
+
+impl DeviceCommand {
+    /// Whether the address segments after `/devices/<set>/` are of this command, the placeholders matching any
+    pub fn matches(&self, segments: &[&str]) -> bool {
+        self.path.split('/').count() == segments.len()
+            && self.path.split('/').zip(segments).all(|(pattern, segment)| pattern.starts_with('{') || pattern == *segment)
+    }
+
+    /// Addresses after `/devices/<set>/` with the placeholders filled in for the device,
+    /// or for any device of a set (index 0 of every type)
+    fn addresses(&self, device: Option<&Device>) -> Vec<Vec<String>> {
+        let pattern: Vec<&str> = self.path.split('/').collect();
+        let mut addresses = vec![Vec::new()];
+        for (i, segment) in pattern.iter().enumerate() {
+            addresses = addresses.into_iter().flat_map(|address: Vec<String>| {
+                let values: Vec<String> = match *segment {
+                    "{actuator}" => SCALAR_ACTUATORS.iter()
+                        .filter(|(_, actuator_type)| device.is_none_or(|device| actuator_count(device, *actuator_type) > 0))
+                        .map(|(name, _)| String::from(*name))
+                        .collect(),
+                    "{sensor}" => SENSOR_TYPES.iter()
+                        .filter(|(_, sensor_type)| device.is_none_or(|device| sensor_count(device, *sensor_type) > 0))
+                        .map(|(name, _)| String::from(*name))
+                        .collect(),
+                    "{index}" => {
+                        let of_type = address.last().map_or("", |name| name.as_str());
+                        let count = match (device, i.checked_sub(1).map(|i| pattern[i])) {
+                            (None, _) => 1,
+                            (Some(device), Some("{actuator}")) => SCALAR_ACTUATORS.iter()
+                                .find(|(name, _)| *name == of_type)
+                                .map_or(0, |(_, actuator_type)| actuator_count(device, *actuator_type)),
+                            (Some(device), Some("{sensor}")) => SENSOR_TYPES.iter()
+                                .find(|(name, _)| *name == of_type)
+                                .map_or(0, |(_, sensor_type)| sensor_count(device, *sensor_type)),
+                            _ => 0,
+                        };
+                        (0..count).map(|index| index.to_string()).collect()
+                    }
+                    _ => vec![String::from(*segment)],
+                };
+                values.into_iter().map(move |value| {
+                    let mut address = address.clone();
+                    address.push(value);
+                    address
+                })
+            }).collect();
+        }
+        addresses
+    }
+}
+
+/// Of the type, as indexed by `scalar/<type>/<index>`
+fn actuator_count(device: &Device, actuator_type: ActuatorType) -> usize {
+    let attributes = device.message_attributes();
+    match actuator_type {
+        ActuatorType::Rotate => attributes.rotate_cmd().as_ref().map_or(0, |rotate| rotate.len()),
+        _ => attributes.scalar_cmd().iter().flatten()
+            .filter(|attribute| *attribute.actuator_type() == actuator_type)
+            .count(),
+    }
+}
+
+/// Of the type, as indexed by `sensor/<type>/<index>`
+fn sensor_count(device: &Device, sensor_type: SensorType) -> usize {
+    device.message_attributes().sensor_subscribe_cmd().iter().flatten()
+        .filter(|attribute| *attribute.sensor_type() == sensor_type)
+        .count()
+}
+
+impl Node {
+    fn container(full_path: String) -> Self {
+        Node { full_path, access: ACCESS_NONE, contents: Some(BTreeMap::new()), ..Default::default() }
+    }
+
+    fn method(full_path: String, description: &'static str, osc_type: Option<&'static str>, range: Option<Vec<Range>>) -> Self {
+        Node { full_path, description: Some(description), access: ACCESS_WRITE, osc_type, range, ..Default::default() }
+    }
+
+    /// Returns the child node, creating a container if it doesn't exist
+    fn child(&mut self, name: &str) -> &mut Node {
+        let full_path = format!("{}/{}", self.full_path.trim_end_matches('/'), name);
+        self.contents.get_or_insert_with(BTreeMap::new)
+            .entry(name.to_string())
+            .or_insert_with(|| Node::container(full_path))
+    }
+
+    /// Makes the node at the path under this one a method, keeping the nodes under it
+    fn method_at(&mut self, path: &[String], command: &DeviceCommand) {
+        let mut node = self;
+        for name in path {
+            node = node.child(name);
+        }
+        node.description = Some(command.description);
+        node.access = ACCESS_WRITE;
+        node.osc_type = command.osc_type;
+        node.range = if command.range.is_empty() { None } else { Some(command.range.to_vec()) };
+        if node.contents.as_ref().is_some_and(|contents| contents.is_empty()) {
+            node.contents = None;
+        }
+    }
+
+    fn insert(&mut self, name: &str, node: Node) {
+        self.contents.get_or_insert_with(BTreeMap::new).insert(name.to_string(), node);
+    }
+
+    fn find(&self, path: &str) -> Option<&Node> {
+        let mut node = self;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            node = node.contents.as_ref()?.get(name)?;
+        }
+        Some(node)
+    }
+}
+
+/// Describes every address accepted by `validate_osc_message` for the currently known devices and the configured sets
+pub fn address_space(devices: &evmap::ReadHandle<&'static str, Device>, mappings: &RwLock<Mappings>, namespace: &str) -> Node {
+    let mut root = Node::container(String::from("/"));
+    let mut namespace_node = &mut root;
+    for name in namespace.split('/').filter(|name| !name.is_empty()) {
+        namespace_node = namespace_node.child(name);
+    }
+    let stop_path = format!("{}/stop", namespace_node.full_path.trim_end_matches('/'));
+    namespace_node.insert("stop", Node::method(
+        stop_path, "Stop all devices on every connected server", None, None,
+    ));
+    let estop_path = format!("{}/estop", namespace_node.full_path.trim_end_matches('/'));
+    namespace_node.insert("estop", Node::method(
+        estop_path, "Stop all devices and ignore the commands until /estop/reset", None, None,
+    ));
+    let estop_node = namespace_node.child("estop");
+    let path = format!("{}/reset", estop_node.full_path);
+    estop_node.insert("reset", Node::method(path, "Accept the commands again after /estop", None, None));
+    let session_node = namespace_node.child("session");
+    let path = format!("{}/reset", session_node.full_path);
+    session_node.insert("reset", Node::method(path, "Start a new session after --session-limit", None, None));
+    let status_path = format!("{}/status", namespace_node.full_path.trim_end_matches('/'));
+    namespace_node.insert("status", Node::method(
+        status_path, "Reply with the connection state of every server via --osc-send", None, None,
+    ));
+    for (command, description) in [
+        ("start", "Start scanning for devices on every connected server"),
+        ("stop", "Stop scanning for devices"),
+        ("status", "Reply with /scan/status True while scanning via --osc-send"),
+    ].iter() {
+        let scan_node = namespace_node.child("scan");
+        let path = format!("{}/{}", scan_node.full_path, command);
+        scan_node.insert(command, Node::method(path, description, None, None));
+    }
+    let dump_path = format!("{}/dump", namespace_node.full_path.trim_end_matches('/'));
+    namespace_node.insert("dump", Node::method(
+        dump_path, "Write this description to the --dump-address-space file", None, None,
+    ));
+    let profile_node = namespace_node.child("profile");
+    let path = format!("{}/select", profile_node.full_path);
+    profile_node.insert("select", Node::method(
+        path, "Switch the routes to the named profile from --config", Some("s"), None,
+    ));
+    let devices_node = namespace_node.child("devices");
+    // the sets, described for any device, then the registered devices by their actual actuators and sensors
+    let mut names: BTreeMap<String, Option<Device>> = BTreeMap::new();
+    {
+        let mappings = mappings.read().expect("unexpected");
+        let sets = [DEVICES_ALL, DEVICES_FIRST, DEVICES_NEWEST, DEVICES_LAST].iter().map(|name| String::from(*name))
+            .chain(mappings.aliases.keys().cloned())
+            .chain(mappings.groups.keys().cloned())
+            .chain(mappings.virtual_devices.keys().cloned());
+        names.extend(sets.map(|name| (name, None)));
+    }
+    if let Some(map) = devices.read() {
+        names.extend(map.iter()
+            .filter(|(k, _)| **k != DEVICES_LAST)
+            .filter_map(|(k, v)| v.get_one().map(|device| (String::from(*k), Some(device.clone())))));
+    }
+    for (name, device) in names {
+        // <server>/<name> when there are several servers
+        let mut device_node = &mut *devices_node;
+        for name in name.split('/') {
+            device_node = device_node.child(name);
+        }
+        for command in DEVICE_COMMANDS.iter() {
+            for address in command.addresses(device.as_ref()) {
+                device_node.method_at(&address, command);
+            }
+        }
+    }
+    root
+}
+
+/// Writes the address space to the file, e.g. for the tools generating controllers offline
+pub fn dump_address_space(path: &Path, devices: &evmap::ReadHandle<&'static str, Device>, mappings: &RwLock<Mappings>, namespace: &str) {
+    let root = address_space(devices, mappings, namespace);
+    match std::fs::write(path, serde_json::to_string_pretty(&root).expect("unexpected")) {
+        Ok(()) => info!("[OSCQuery] written to {}", path.display()),
+        Err(e) => warn!("[OSCQuery] {}: {}", path.display(), e),
+    }
+}
+
+/// Writes the address space to the file at the start and whenever a device is added or removed
+pub async fn dump_address_space_on_change(
+    path: PathBuf,
+    devices: evmap::ReadHandle<&'static str, Device>,
+    mappings: Arc<RwLock<Mappings>>,
+    namespace: String,
+    mut events: broadcast::Receiver<(String, Vec<OscType>)>,
+) {
+    dump_address_space(&path, &devices, &mappings, &namespace);
+    loop {
+        match events.recv().await {
+            Ok((addr, _)) if addr == "/events/device/added" || addr == "/events/device/removed" => {
+                dump_address_space(&path, &devices, &mappings, &namespace);
+            }
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+fn host_info(name: &str, osc_port: u16) -> serde_json::Value {
+    serde_json::json!({
+        "NAME": name,
+        "OSC_PORT": osc_port,
+        "OSC_TRANSPORT": "UDP",
+        "EXTENSIONS": {
+            "ACCESS": true,
+            "VALUE": false,
+            "RANGE": true,
+            "DESCRIPTION": true,
+        },
+    })
+}
+
+pub fn oscquery_listen(
+    host_port: &str, name: &str, osc_port: u16,
+    devices: evmap::ReadHandle<&'static str, Device>, mappings: Arc<RwLock<Mappings>>, namespace: &str,
+) {
+    let server = tiny_http::Server::http(host_port).expect("Invalid --oscquery-listen: couldn't bind socket");
+    let content_type = tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("unexpected");
+    for request in server.incoming_requests() {
+        debug!("[OSCQuery] {} {}", request.method(), request.url());
+        let (path, query) = match request.url().split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (request.url().to_string(), None),
+        };
+        let body = match query.as_deref() {
+            Some("HOST_INFO") => Some(host_info(name, osc_port).to_string()),
+            _ => {
+                let root = address_space(&devices, &mappings, namespace);
+                root.find(&path).map(|node| serde_json::to_string(node).expect("unexpected"))
+            }
+        };
+        let result = match body {
+            Some(body) => request.respond(
+                tiny_http::Response::from_string(body).with_header(content_type.clone())
+            ),
+            None => request.respond(tiny_http::Response::empty(404)),
+        };
+        if let Err(e) = result {
+            warn!("[OSCQuery] {:?}", e);
+        }
+    }
+}
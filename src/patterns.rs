@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time;
+use rand::Rng;
+use buttplug::client::device::LinearCommand;
+use tracing::{debug, error, warn};
+use crate::{Device, Speed, Position, Millis, normalize_device_name};
+use crate::config::{Config, PatternConfig};
+use crate::session::Session;
+use crate::state::{self, DeviceStates};
+
+/// Vibrates at the speed for the duration, then returns to the baseline speed
+pub async fn pulse(device: Device, states: DeviceStates, speed: Speed, duration: Millis, baseline: Speed) {
+    let _ = state::vibrate(&device, &states, speed).await;
+    time::sleep(Duration::from_millis(duration.into())).await;
+    let _ = state::vibrate(&device, &states, baseline).await;
+}
+
+/// Linearly changes the vibration speed from the current one to the target over the duration
+pub async fn ramp(device: Device, states: DeviceStates, target: Speed, duration: Millis, tick_rate: f64) {
+    let from = states.speed(&normalize_device_name(device.name()));
+    let ticks = ((f64::from(duration) / 1000.0 * tick_rate).round() as u64).max(1);
+    let mut interval = time::interval(Duration::from_secs_f64(1.0 / tick_rate));
+    interval.tick().await;
+    for tick in 1..=ticks {
+        interval.tick().await;
+        let speed = from + (target - from) * tick as f64 / ticks as f64;
+        let _ = state::vibrate(&device, &states, speed).await;
+    }
+}
+
+/// Toggles between the speed and zero at the frequency, spending `duty` fraction of each period at the speed.
+/// The frequency is limited so that the device receives at most `tick_rate` commands per second
+pub async fn pwm(device: Device, states: DeviceStates, speed: Speed, frequency: f64, duty: f64, tick_rate: f64) {
+    let period = 1.0 / frequency.min(tick_rate / 2.0);
+    let duty = duty.max(0.0).min(1.0);
+    let on = Duration::from_secs_f64(period * duty);
+    let off = Duration::from_secs_f64(period * (1.0 - duty));
+    loop {
+        if on > Duration::ZERO {
+            let _ = state::vibrate(&device, &states, speed).await;
+            time::sleep(on).await;
+        }
+        if off > Duration::ZERO {
+            let _ = state::vibrate(&device, &states, 0.0).await;
+            time::sleep(off).await;
+        }
+    }
+}
+
+/// Vibrates at each speed at its offset in milliseconds from the start
+pub async fn sequence(device: Device, states: DeviceStates, steps: Vec<(Millis, Speed)>) {
+    let start = time::Instant::now();
+    for (at, speed) in steps {
+        time::sleep_until(start + Duration::from_millis(at.into())).await;
+        let _ = state::vibrate(&device, &states, speed).await;
+    }
+}
+
+/// Duration of a single move at the lowest and highest stroke speed
+const STROKE_SLOWEST: Millis = 2000;
+const STROKE_FASTEST: Millis = 200;
+
+/// Moves the linear actuators back and forth between the positions,
+/// a single move lasts from 2 seconds at the speed 0.0 to 0.2 seconds at 1.0
+pub async fn stroke(device: Device, speed: Speed, min_position: Position, max_position: Position) {
+    let speed = speed.max(0.0).min(1.0);
+    let duration = STROKE_SLOWEST - ((STROKE_SLOWEST - STROKE_FASTEST) as f64 * speed).round() as Millis;
+    for &position in [max_position, min_position].iter().cycle() {
+        let _ = device.linear(&LinearCommand::Linear(duration, position)).await.map_err(|e|
+            error!("{:?}", e)
+        );
+        time::sleep(Duration::from_millis(duration.into())).await;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Waveform {
+    Sine,
+    Triangle,
+    Square,
+}
+
+impl Waveform {
+    /// From -1.0 to 1.0 at the phase from 0.0 to 1.0
+    fn value(self, phase: f64) -> f64 {
+        match self {
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+        }
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            "square" => Ok(Waveform::Square),
+            _ => Err(format!("invalid waveform: {}", s)),
+        }
+    }
+}
+
+/// Modulates the vibration speed around the offset by the waveform scaled to the depth, updated `tick_rate` times per second
+pub async fn lfo(device: Device, states: DeviceStates, waveform: Waveform, frequency: f64, depth: f64, offset: f64, tick_rate: f64) {
+    let start = Instant::now();
+    let mut interval = time::interval(Duration::from_secs_f64(1.0 / tick_rate));
+    let mut last_speed = None;
+    loop {
+        interval.tick().await;
+        let phase = (start.elapsed().as_secs_f64() * frequency).fract();
+        let speed = (offset + depth * waveform.value(phase)).max(0.0).min(1.0);
+        if last_speed != Some(speed) {
+            let _ = state::vibrate(&device, &states, speed).await;
+            last_speed = Some(speed);
+        }
+    }
+}
+
+pub enum Pattern {
+    Steps {
+        /// Speed to reach and duration to hold (or, if smooth, to reach) it
+        steps: Vec<(Speed, Millis)>,
+        smooth: bool,
+        repeat: bool,
+    },
+    Random {
+        min: Speed,
+        max: Speed,
+        interval: Millis,
+    },
+}
+
+impl Pattern {
+    /// Err if a step has a speed outside 0..1, or the steps take no time, which would repeat in a busy loop
+    pub fn from_config(config: &PatternConfig) -> Result<Self, String> {
+        if config.steps.is_empty() {
+            return Err(String::from("no steps"));
+        }
+        if let Some(&(speed, _)) = config.steps.iter().find(|&&(speed, _)| !(0.0..=1.0).contains(&speed)) {
+            return Err(format!("speed {} must be within 0..1", speed));
+        }
+        if config.steps.iter().all(|&(_, duration)| duration == 0) {
+            return Err(String::from("total duration must be positive"));
+        }
+        Ok(Pattern::Steps { steps: config.steps.clone(), smooth: config.smooth, repeat: config.repeat })
+    }
+}
+
+/// Built-in patterns, overridden by the user-defined ones with the same name
+pub fn load_patterns(config: &Config) -> Result<HashMap<String, Arc<Pattern>>, String> {
+    let mut patterns = HashMap::new();
+    patterns.insert(String::from("wave"), Pattern::Steps {
+        steps: vec![(1.0, 2000), (0.1, 2000)],
+        smooth: true,
+        repeat: true,
+    });
+    patterns.insert(String::from("heartbeat"), Pattern::Steps {
+        steps: vec![(1.0, 100), (0.0, 150), (0.7, 100), (0.0, 650)],
+        smooth: false,
+        repeat: true,
+    });
+    patterns.insert(String::from("escalate"), Pattern::Steps {
+        steps: vec![(0.2, 10000), (0.4, 10000), (0.6, 10000), (0.8, 10000), (1.0, 10000)],
+        smooth: false,
+        repeat: false,
+    });
+    patterns.insert(String::from("random"), Pattern::Random {
+        min: 0.2,
+        max: 1.0,
+        interval: 500,
+    });
+    for (name, pattern) in config.patterns.iter() {
+        let pattern = Pattern::from_config(pattern).map_err(|e| format!("patterns.{}: {}", name, e))?;
+        patterns.insert(name.clone(), pattern);
+    }
+    Ok(patterns.into_iter().map(|(name, pattern)| (name, Arc::new(pattern))).collect())
+}
+
+/// Plays the pattern until the next command; a non-repeating one holds its last speed
+pub async fn play(device: Device, states: DeviceStates, pattern: Arc<Pattern>, tick_rate: f64) {
+    match *pattern {
+        Pattern::Steps { ref steps, smooth, repeat } => {
+            loop {
+                for &(speed, duration) in steps.iter() {
+                    if smooth {
+                        ramp(device.clone(), states.clone(), speed, duration, tick_rate).await;
+                    } else {
+                        let _ = state::vibrate(&device, &states, speed).await;
+                        time::sleep(Duration::from_millis(duration.into())).await;
+                    }
+                }
+                if !repeat || steps.is_empty() {
+                    break;
+                }
+            }
+        }
+        Pattern::Random { min, max, interval } => {
+            loop {
+                let speed = rand::thread_rng().gen_range(min..=max);
+                let _ = state::vibrate(&device, &states, speed).await;
+                time::sleep(Duration::from_millis(interval.into())).await;
+            }
+        }
+    }
+}
+
+/// Patterns which start playing whenever their device appears, by normalized device name
+pub struct DefaultPatterns {
+    patterns: HashMap<String, Arc<Pattern>>,
+    states: DeviceStates,
+    session: Option<Arc<Session>>,
+    tick_rate: f64,
+}
+
+impl DefaultPatterns {
+    pub fn new(
+        config: &Config, patterns: &HashMap<String, Arc<Pattern>>, states: DeviceStates, session: Option<Arc<Session>>, tick_rate: f64,
+    ) -> Self {
+        let mut defaults = HashMap::new();
+        for (device_name, device) in config.device.iter() {
+            if let Some(ref name) = device.pattern {
+                match patterns.get(name) {
+                    Some(pattern) => {
+                        defaults.insert(device_name.clone(), pattern.clone());
+                    }
+                    None => warn!("[{}] unknown pattern: {}", device_name, name),
+                }
+            }
+        }
+        DefaultPatterns { patterns: defaults, states, session, tick_rate }
+    }
+
+    pub fn start(&self, device: &Device) {
+        let device_name = normalize_device_name(device.name());
+        if self.states.estop() || self.session.as_ref().is_some_and(|session| session.ended()) {
+            return;
+        }
+        if let Some(pattern) = self.patterns.get(&device_name) {
+            debug!("[{}] playing default pattern", device_name);
+            self.states.set_baseline(&device_name, 0.0);
+            let play = play(device.clone(), self.states.clone(), pattern.clone(), self.tick_rate);
+            self.states.set_task(&device_name, tokio::task::spawn(play));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(config: &str) -> Result<HashMap<String, Arc<Pattern>>, String> {
+        load_patterns(&toml::from_str(config).expect("unexpected"))
+    }
+
+    #[test]
+    fn user_defined_patterns_override_the_built_in_ones() {
+        let patterns = patterns("[patterns.wave]\nsteps = [[0.5, 100]]").expect("unexpected");
+        assert!(patterns.contains_key("escalate"));
+        match *patterns["wave"] {
+            Pattern::Steps { ref steps, smooth, repeat } => assert_eq!((steps.as_slice(), smooth, repeat), (&[(0.5, 100)][..], false, true)),
+            Pattern::Random { .. } => panic!("unexpected"),
+        }
+    }
+
+    #[test]
+    fn busy_loops_and_invalid_speeds_are_rejected() {
+        assert!(patterns("[patterns.empty]\nsteps = []").is_err());
+        assert!(patterns("[patterns.instant]\nsteps = [[1.0, 0], [0.0, 0]]").is_err());
+        assert!(patterns("[patterns.loud]\nsteps = [[1.5, 100]]").is_err());
+        assert!(patterns("[patterns.negative]\nsteps = [[-0.5, 100]]").is_err());
+        // a step may take no time
+        assert!(patterns("[patterns.jump]\nsteps = [[1.0, 0], [0.0, 100]]").is_ok());
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use nannou_osc as osc;
+use nannou_osc::rosc::OscPacket;
+use tracing::warn;
+use crate::config;
+use crate::metrics::METRICS;
+
+/// How often the buckets of the senders gone quiet are forgotten
+const EVICT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token bucket per source address, refilled at `rate` messages per second up to a burst of `rate` messages;
+/// a bundle of more messages than that is accepted when the bucket is full, and paid back by the following ones
+pub struct RateLimiter {
+    rate: f64,
+    buckets: HashMap<SocketAddr, TokenBucket>,
+    evicted: Instant,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+    dropped: u64,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64) -> Self {
+        config::check_positive(rate).unwrap_or_else(|e| panic!("Invalid --osc-rate-limit: {}", e));
+        RateLimiter { rate, buckets: HashMap::new(), evicted: Instant::now() }
+    }
+
+    /// Whether the packet fits the limit; bundles are accepted or dropped as a whole
+    pub fn check(&mut self, source: SocketAddr, packet: &osc::Packet) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.evicted) >= EVICT_INTERVAL {
+            self.evict(now);
+        }
+        self.take(source, count_messages(packet), now)
+    }
+
+    fn take(&mut self, source: SocketAddr, count: usize, now: Instant) -> bool {
+        let cost = count as f64;
+        let rate = self.rate;
+        let bucket = self.buckets.entry(source).or_insert(TokenBucket { tokens: rate, updated: now, dropped: 0 });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(rate);
+        bucket.updated = now;
+        if bucket.tokens >= cost.min(rate) {
+            bucket.tokens -= cost;
+            if bucket.dropped > 0 {
+                warn!("[{}] dropped {} messages over --osc-rate-limit", source, bucket.dropped);
+                bucket.dropped = 0;
+            }
+            true
+        } else {
+            if bucket.dropped == 0 {
+                warn!("[{}] exceeded --osc-rate-limit, dropping messages", source);
+            }
+            bucket.dropped += count as u64;
+            METRICS.rate_limited(count as u64);
+            false
+        }
+    }
+
+    /// Forgets the senders quiet for a while, their buckets would be full again anyway
+    fn evict(&mut self, now: Instant) {
+        self.buckets.retain(|source, bucket| {
+            let idle = now.duration_since(bucket.updated) >= EVICT_INTERVAL;
+            if idle && bucket.dropped > 0 {
+                warn!("[{}] dropped {} messages over --osc-rate-limit", source, bucket.dropped);
+            }
+            !idle
+        });
+        self.evicted = now;
+    }
+}
+
+fn count_messages(packet: &osc::Packet) -> usize {
+    match packet {
+        osc::Packet::Message(_) => 1,
+        osc::Packet::Bundle(bundle) => bundle.content.iter().map(count_elements).sum(),
+    }
+}
+
+fn count_elements(packet: &OscPacket) -> usize {
+    match packet {
+        OscPacket::Message(_) => 1,
+        OscPacket::Bundle(bundle) => bundle.content.iter().map(count_elements).sum(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> SocketAddr {
+        "127.0.0.1:9000".parse().expect("unexpected")
+    }
+
+    #[test]
+    fn drops_over_the_rate_until_refilled() {
+        let mut limiter = RateLimiter::new(2.0);
+        let now = Instant::now();
+        assert!(limiter.take(source(), 1, now));
+        assert!(limiter.take(source(), 1, now));
+        assert!(!limiter.take(source(), 1, now));
+        assert!(limiter.take(source(), 1, now + Duration::from_millis(500)));
+        assert!(!limiter.take(source(), 1, now + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn senders_have_separate_buckets() {
+        let mut limiter = RateLimiter::new(1.0);
+        let now = Instant::now();
+        let other = "127.0.0.1:9001".parse().expect("unexpected");
+        assert!(limiter.take(source(), 1, now));
+        assert!(!limiter.take(source(), 1, now));
+        assert!(limiter.take(other, 1, now));
+    }
+
+    #[test]
+    fn oversize_bundle_is_accepted_on_a_full_bucket_and_paid_back() {
+        let mut limiter = RateLimiter::new(2.0);
+        let now = Instant::now();
+        assert!(limiter.take(source(), 5, now));
+        // 3 messages in debt, back to 1 after 2 seconds
+        assert!(!limiter.take(source(), 1, now + Duration::from_secs(1)));
+        assert!(limiter.take(source(), 1, now + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn oversize_bundle_is_dropped_on_a_partial_bucket() {
+        let mut limiter = RateLimiter::new(2.0);
+        let now = Instant::now();
+        assert!(limiter.take(source(), 1, now));
+        assert!(!limiter.take(source(), 5, now));
+    }
+
+    #[test]
+    fn idle_senders_are_evicted() {
+        let mut limiter = RateLimiter::new(1.0);
+        let now = Instant::now();
+        limiter.take(source(), 1, now);
+        limiter.evict(now + EVICT_INTERVAL / 2);
+        assert_eq!(limiter.buckets.len(), 1);
+        limiter.evict(now + EVICT_INTERVAL);
+        assert!(limiter.buckets.is_empty());
+    }
+
+    #[test]
+    fn bundles_count_their_messages() {
+        let message = || OscPacket::Message(osc::Message { addr: String::from("/a"), args: None });
+        let nested = OscPacket::Bundle(osc::Bundle { timetag: osc::Type::Time(0, 1), content: vec![message(), message()] });
+        let bundle = osc::Packet::Bundle(osc::Bundle { timetag: osc::Type::Time(0, 1), content: vec![message(), nested] });
+        assert_eq!(count_messages(&bundle), 3);
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+use crate::config::{self, Mappings};
+use crate::shaping::Shaping;
+use crate::state::DeviceStates;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Applies the aliases, groups, virtual devices, routes and per-device shaping (curves, clamps, motors)
+/// from the --config file whenever it's modified, or on SIGHUP.
+/// Other settings take effect only after a restart
+pub async fn watch(path: PathBuf, mappings: Arc<RwLock<Mappings>>, states: DeviceStates) {
+    let mut hangup = hangup();
+    let mut last_modified = modified(&path);
+    loop {
+        let signaled = tokio::select! {
+            _ = tokio::time::sleep(POLL_INTERVAL) => false,
+            _ = hangup.recv() => true,
+        };
+        let current = modified(&path);
+        if !signaled && current == last_modified {
+            continue;
+        }
+        last_modified = current;
+        let parsed = config::parse(&path)
+            .and_then(|config| Ok((Mappings::from_config(&config)?, Shaping::from_config(&config)?)));
+        match parsed {
+            Ok((mut new_mappings, shaping)) => {
+                let mut current_mappings = mappings.write().expect("unexpected");
+                // keeping the profile selected via OSC
+                if let Some(profile) = current_mappings.profile() {
+                    new_mappings.select_profile(profile);
+                }
+                *current_mappings = new_mappings;
+                states.set_shaping(shaping);
+                info!("Reloaded --config {}", path.display());
+            }
+            Err(e) => warn!("Invalid --config, keeping the previous settings: {}", e),
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Receives SIGHUP; never on Windows
+struct Hangup {
+    #[cfg(unix)]
+    signal: tokio::signal::unix::Signal,
+}
+
+fn hangup() -> Hangup {
+    #[cfg(unix)]
+    {
+        let signal = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .expect("couldn't listen for SIGHUP");
+        Hangup { signal }
+    }
+    #[cfg(not(unix))]
+    Hangup {}
+}
+
+impl Hangup {
+    #[cfg(unix)]
+    async fn recv(&mut self) {
+        self.signal.recv().await;
+    }
+
+    #[cfg(not(unix))]
+    async fn recv(&mut self) {
+        futures_util::future::pending::<()>().await
+    }
+}
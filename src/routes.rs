@@ -0,0 +1,548 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{self, AtomicU64};
+use std::time::{Duration, Instant};
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+use regex::Regex;
+use rhai::{Engine, OptimizationLevel, Scope, AST};
+use tracing::warn;
+use crate::config::{Combine, RouteConfig};
+use crate::device_filter::wildcard_match;
+use crate::vrchat;
+
+/// Most operations of a transform evaluation, so that a runaway loop can't stall the dispatch
+const MAX_OPERATIONS: u64 = 10_000;
+/// Deepest nesting of a transform expression, and of a function body in it
+const MAX_EXPR_DEPTH: usize = 32;
+
+/// For the route transforms, with the evaluation limited
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH);
+    // the constant parts would be evaluated when compiled, without the limits
+    engine.set_optimization_level(OptimizationLevel::None);
+    engine
+}
+
+/// Route with its compiled transform and condition
+pub struct Route {
+    config: RouteConfig,
+    transform: Option<AST>,
+    when: Option<Condition>,
+}
+
+/// `<address> <operator> <value>`
+struct Condition {
+    address: String,
+    /// Orderings of the latest value to the one given which satisfy the condition
+    orderings: &'static [Ordering],
+    value: f64,
+}
+
+impl Condition {
+    fn parse(condition: &str) -> Result<Self, String> {
+        let regex = Regex::new(r"^\s*(\S+)\s*(==|!=|<=|>=|<|>)\s*(\S+)\s*$").expect("unexpected");
+        let captures = regex.captures(condition).ok_or_else(|| String::from("expected <address> <operator> <value>"))?;
+        let orderings: &'static [Ordering] = match &captures[2] {
+            "==" => &[Ordering::Equal],
+            "!=" => &[Ordering::Less, Ordering::Greater],
+            "<" => &[Ordering::Less],
+            "<=" => &[Ordering::Less, Ordering::Equal],
+            ">" => &[Ordering::Greater],
+            _ => &[Ordering::Greater, Ordering::Equal],
+        };
+        let value = match &captures[3] {
+            "true" => 1.0,
+            "false" => 0.0,
+            value => value.parse().map_err(|_| format!("invalid value {}", value))?,
+        };
+        Ok(Condition { address: String::from(&captures[1]), orderings, value })
+    }
+
+    fn holds(&self, transforms: &Transforms) -> bool {
+        transforms.value(&self.address)
+            .and_then(|value| value.partial_cmp(&self.value))
+            .is_some_and(|ordering| self.orderings.contains(&ordering))
+    }
+}
+
+impl Route {
+    pub fn new(config: RouteConfig) -> Result<Self, String> {
+        if config.command.is_empty() {
+            return Err(format!("route {}: no command", config.address));
+        }
+        if config.input.is_some_and(|(min, max)| min == max) {
+            return Err(format!("route {}: empty input range", config.address));
+        }
+        if !(0.0..1.0).contains(&config.deadzone) {
+            return Err(format!("route {}: deadzone must be within 0..1", config.address));
+        }
+        if !config.smoothing.is_finite() || config.smoothing < 0.0 {
+            return Err(format!("route {}: smoothing must not be negative", config.address));
+        }
+        let transform = match config.transform {
+            Some(ref script) => Some(
+                engine().compile_expression(script)
+                    .map_err(|e| format!("transform of route {}: {}", config.address, e))?
+            ),
+            None => None,
+        };
+        let when = match config.when {
+            Some(ref condition) => Some(
+                Condition::parse(condition).map_err(|e| format!("condition of route {}: {}", config.address, e))?
+            ),
+            None => None,
+        };
+        Ok(Route { config, transform, when })
+    }
+
+    fn limits(&self) -> Limits {
+        Limits {
+            min_interval: Duration::from_millis(self.config.min_interval_ms.into()),
+            min_change: self.config.min_change,
+        }
+    }
+
+    fn message(&self, args: Option<Vec<OscType>>, namespace: &str) -> osc::Message {
+        osc::Message {
+            addr: format!("{}/devices/{}/{}", namespace, self.config.device, self.config.command.trim_start_matches('/')),
+            args,
+        }
+    }
+}
+
+/// Evaluates the route transforms, remembering the latest values of the received messages
+/// for `params` and the route conditions
+pub struct Transforms {
+    engine: Engine,
+    start: Instant,
+    params: Mutex<rhai::Map>,
+    values: Mutex<HashMap<String, f64>>,
+}
+
+impl Default for Transforms {
+    fn default() -> Self {
+        Transforms { engine: engine(), start: Instant::now(), params: Mutex::default(), values: Mutex::default() }
+    }
+}
+
+impl Transforms {
+    fn remember(&self, addr: &str, value: f64) {
+        let name = addr.rsplit('/').next().unwrap_or(addr);
+        self.params.lock().expect("unexpected").insert(name.into(), value.into());
+        self.values.lock().expect("unexpected").insert(String::from(addr), value);
+    }
+
+    fn value(&self, addr: &str) -> Option<f64> {
+        self.values.lock().expect("unexpected").get(addr).cloned()
+    }
+
+    fn apply(&self, transform: &AST, x: f64) -> Result<f64, String> {
+        let mut scope = Scope::new();
+        scope.push("x", x);
+        scope.push("t", self.start.elapsed().as_secs_f64());
+        scope.push("params", self.params.lock().expect("unexpected").clone());
+        let result = self.engine.eval_ast_with_scope::<rhai::Dynamic>(&mut scope, transform)
+            .map_err(|e| e.to_string())?;
+        result.as_float()
+            .or_else(|_| result.as_int().map(|x| x as f64))
+            .map_err(|type_name| format!("number expected, got {}", type_name))
+    }
+}
+
+/// Routed message, with the time constant of the route smoothing in seconds (zero for none)
+pub struct Routed {
+    pub message: osc::Message,
+    pub smoothing: f64,
+    pub limits: Limits,
+    pub combine: Combine,
+}
+
+/// How long the latest input of a received address is combined without refreshing, e.g. of a sender gone
+const INPUT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Latest first argument and when it was routed, by the received address
+type Inputs = HashMap<String, (f64, Instant)>;
+
+/// Latest first arguments routed to each address, by the received address
+#[derive(Default)]
+pub struct Combiner {
+    inputs: Mutex<HashMap<String, Inputs>>,
+}
+
+impl Combiner {
+    /// Replaces the first argument of the routed message with the combination of the latest ones
+    pub fn combine(&self, received_addr: &str, mut message: osc::Message, combine: Combine) -> osc::Message {
+        let value = match first_value(&message) {
+            Some(value) if combine != Combine::Latest => value,
+            _ => return message,
+        };
+        let mut inputs = self.inputs.lock().expect("unexpected");
+        let inputs = inputs.entry(message.addr.clone()).or_default();
+        let now = Instant::now();
+        inputs.insert(String::from(received_addr), (value, now));
+        inputs.retain(|_, (_, at)| now.duration_since(*at) < INPUT_TIMEOUT);
+        let values = inputs.values().map(|(value, _)| *value);
+        let combined = match combine {
+            Combine::Latest => value,
+            Combine::Max => values.fold(f64::NEG_INFINITY, f64::max),
+            Combine::Sum => values.sum::<f64>().max(-1.0).min(1.0),
+            Combine::Average => values.sum::<f64>() / inputs.len() as f64,
+        };
+        if let Some(first) = message.args.as_mut().and_then(|args| args.first_mut()) {
+            *first = OscType::Double(combined);
+        }
+        message
+    }
+
+    pub fn addresses(&self) -> Vec<String> {
+        self.inputs.lock().expect("unexpected").keys().cloned().collect()
+    }
+
+    /// Forgets the inputs routed to the address, e.g. when its device is stopped
+    pub fn cancel(&self, addr: &str) {
+        self.inputs.lock().expect("unexpected").remove(addr);
+    }
+
+    pub fn clear(&self) {
+        self.inputs.lock().expect("unexpected").clear();
+    }
+}
+
+/// Update rate limits of a route
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    pub min_interval: Duration,
+    pub min_change: f64,
+}
+
+impl Limits {
+    pub fn is_none(&self) -> bool {
+        self.min_interval == Duration::ZERO && self.min_change <= 0.0
+    }
+}
+
+/// Last sent routed messages by address, for the route limits
+#[derive(Default)]
+pub struct Throttle {
+    sent: Mutex<HashMap<String, ThrottledValue>>,
+}
+
+struct ThrottledValue {
+    at: Instant,
+    value: Option<f64>,
+    /// Latest skipped message within the interval, sent afterwards
+    pending: Option<osc::Message>,
+}
+
+pub enum Throttled {
+    Send,
+    Skip,
+    /// The caller should send `Throttle::take_pending` after the delay
+    Later(Duration),
+}
+
+impl Throttle {
+    pub fn check(&self, message: &osc::Message, value: Option<f64>, limits: Limits) -> Throttled {
+        let mut sent = self.sent.lock().expect("unexpected");
+        let now = Instant::now();
+        let last = match sent.get_mut(&message.addr) {
+            Some(last) => last,
+            None => {
+                sent.insert(message.addr.clone(), ThrottledValue { at: now, value, pending: None });
+                return Throttled::Send;
+            }
+        };
+        if let (Some(value), Some(last_value)) = (value, last.value) {
+            let to_zero = value == 0.0 && last_value != 0.0;
+            if !to_zero && (value - last_value).abs() < limits.min_change {
+                last.pending = None;
+                return Throttled::Skip;
+            }
+        }
+        let elapsed = now.duration_since(last.at);
+        if elapsed >= limits.min_interval {
+            *last = ThrottledValue { at: now, value, pending: None };
+            return Throttled::Send;
+        }
+        match last.pending.replace(message.clone()) {
+            Some(_) => Throttled::Skip,
+            None => Throttled::Later(limits.min_interval - elapsed),
+        }
+    }
+
+    pub fn take_pending(&self, addr: &str) -> Option<osc::Message> {
+        let mut sent = self.sent.lock().expect("unexpected");
+        let last = sent.get_mut(addr)?;
+        let message = last.pending.take()?;
+        last.at = Instant::now();
+        last.value = first_value(&message);
+        Some(message)
+    }
+}
+
+pub fn first_value(message: &osc::Message) -> Option<f64> {
+    match message.args.as_ref().and_then(|args| args.first()) {
+        Some(OscType::Double(x)) => Some(*x),
+        Some(OscType::Float(x)) => Some(f64::from(*x)),
+        _ => None,
+    }
+}
+
+/// First arguments of the smoothed routed messages by address, approaching the received ones
+#[derive(Default)]
+pub struct Smoothing {
+    values: Mutex<HashMap<String, SmoothedValue>>,
+    tasks: AtomicU64,
+}
+
+struct SmoothedValue {
+    current: f64,
+    target: f64,
+    args: Vec<OscType>,
+    /// Task sending the approaching values, if any
+    task: Option<u64>,
+}
+
+impl Smoothing {
+    /// Sets the new target; the task the caller should start sending the approaching values via `step`, if none is
+    pub fn set_target(&self, message: &osc::Message, target: f64) -> Option<u64> {
+        let mut values = self.values.lock().expect("unexpected");
+        let value = values.entry(message.addr.clone()).or_insert_with(|| SmoothedValue {
+            current: 0.0, target, args: Vec::new(), task: None,
+        });
+        value.target = target;
+        value.args = message.args.clone().unwrap_or_default();
+        if value.task.is_some() {
+            return None;
+        }
+        let task = self.tasks.fetch_add(1, atomic::Ordering::Relaxed);
+        value.task = Some(task);
+        Some(task)
+    }
+
+    /// Moves the value of the address towards its target, returns the message to send
+    /// and whether the target has been reached; none once the task should stop, e.g. cancelled
+    pub fn step(&self, addr: &str, task: u64, elapsed: f64, time_constant: f64) -> Option<(osc::Message, bool)> {
+        let mut values = self.values.lock().expect("unexpected");
+        let value = values.get_mut(addr).filter(|value| value.task == Some(task))?;
+        value.current += (value.target - value.current) * (1.0 - (-elapsed / time_constant).exp());
+        let reached = (value.target - value.current).abs() < 0.001;
+        if reached {
+            value.current = value.target;
+            value.task = None;
+        }
+        let mut args = value.args.clone();
+        if let Some(first) = args.first_mut() {
+            *first = OscType::Double(value.current);
+        }
+        Some((osc::Message { addr: String::from(addr), args: Some(args) }, reached))
+    }
+
+    pub fn addresses(&self) -> Vec<String> {
+        self.values.lock().expect("unexpected").keys().cloned().collect()
+    }
+
+    /// Stops approaching the target of the address, e.g. when its device is stopped;
+    /// the next target is approached from zero
+    pub fn cancel(&self, addr: &str) {
+        self.values.lock().expect("unexpected").remove(addr);
+    }
+
+    pub fn clear(&self) {
+        self.values.lock().expect("unexpected").clear();
+    }
+}
+
+/// Rewrites the message to `/devices/<device>/<command>` for every route matching its address,
+/// with the first argument (float, bool as 0..1, or int if the route has input range or it's a --vrchat parameter) remapped
+pub fn route<'a>(
+    routes: impl Iterator<Item = &'a Route>, transforms: &Transforms, message: &osc::Message, namespace: &str, vrchat: bool,
+) -> Vec<Routed> {
+    let first = message.args.as_ref().and_then(|args| args.first());
+    let (value, is_int) = match first {
+        Some(OscType::Float(x)) => (Some(f64::from(*x)), false),
+        Some(OscType::Double(x)) => (Some(*x), false),
+        Some(OscType::Bool(x)) => (Some(if *x { 1.0 } else { 0.0 }), false),
+        Some(OscType::Int(x)) => (Some(f64::from(*x)), true),
+        Some(OscType::Long(x)) => (Some(*x as f64), true),
+        _ => (None, false),
+    };
+    let vrchat_value = match value {
+        Some(value) if is_int && vrchat => vrchat::int_parameter(&message.addr, value),
+        _ => None,
+    };
+    if let Some(value) = vrchat_value.or(value) {
+        transforms.remember(&message.addr, value);
+    }
+    routes
+        .filter(|route| wildcard_match(&route.config.address, &message.addr))
+        .filter(|route| route.when.as_ref().is_none_or(|when| when.holds(transforms)))
+        .filter_map(|route| {
+            let route_value = match (value, vrchat_value) {
+                (Some(value), _) if !is_int || route.config.input.is_some() => value,
+                (_, Some(value)) => value,
+                _ => return Some(Routed {
+                    message: route.message(message.args.clone(), namespace),
+                    smoothing: 0.0,
+                    limits: route.limits(),
+                    combine: Combine::Latest,
+                }),
+            };
+            let x = route.config.normalize(route_value);
+            let x = match route.transform {
+                Some(ref transform) => match transforms.apply(transform, x) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        warn!("[{}] transform of route {}: {}", message.addr, route.config.address, e);
+                        return None;
+                    }
+                },
+                None => x,
+            };
+            let mut args = message.args.clone().unwrap_or_default();
+            args[0] = OscType::Double(route.config.scale(x));
+            Some(Routed {
+                message: route.message(Some(args), namespace),
+                smoothing: route.config.smoothing,
+                limits: route.limits(),
+                combine: route.config.combine,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(value: f32) -> osc::Message {
+        osc::Message { addr: String::from("/devices/Hush/vibrate/speed"), args: Some(vec![OscType::Float(value)]) }
+    }
+
+    fn check(throttle: &Throttle, value: f32, limits: Limits) -> Throttled {
+        let message = message(value);
+        throttle.check(&message, first_value(&message), limits)
+    }
+
+    #[test]
+    fn small_changes_are_skipped() {
+        let throttle = Throttle::default();
+        let limits = Limits { min_interval: Duration::ZERO, min_change: 0.1 };
+        assert!(matches!(check(&throttle, 0.5, limits), Throttled::Send));
+        assert!(matches!(check(&throttle, 0.55, limits), Throttled::Skip));
+        assert!(matches!(check(&throttle, 0.7, limits), Throttled::Send));
+        assert!(matches!(check(&throttle, 0.65, limits), Throttled::Skip));
+        // stopping is never skipped
+        assert!(matches!(check(&throttle, 0.0, limits), Throttled::Send));
+    }
+
+    #[test]
+    fn latest_within_the_interval_is_sent_afterwards() {
+        let throttle = Throttle::default();
+        let limits = Limits { min_interval: Duration::from_secs(3600), min_change: 0.0 };
+        assert!(matches!(check(&throttle, 0.1, limits), Throttled::Send));
+        match check(&throttle, 0.2, limits) {
+            Throttled::Later(delay) => assert!(delay <= limits.min_interval),
+            _ => panic!("expected Later"),
+        }
+        assert!(matches!(check(&throttle, 0.3, limits), Throttled::Skip));
+        assert_eq!(throttle.take_pending(&message(0.0).addr), Some(message(0.3)));
+        assert_eq!(throttle.take_pending(&message(0.0).addr), None);
+    }
+
+    #[test]
+    fn routed_with_the_first_argument_remapped() {
+        let config: RouteConfig = toml::from_str(
+            "match = \"/avatar/parameters/*\"\ndevice = \"Hush\"\ncommand = \"vibrate/speed\"\nscale = [0.0, 0.5]",
+        ).expect("unexpected");
+        let routes = [Route::new(config).expect("unexpected")];
+        let received = osc::Message { addr: String::from("/avatar/parameters/Squeeze"), args: Some(vec![OscType::Float(0.5)]) };
+        let routed = route(routes.iter(), &Transforms::default(), &received, "", false);
+        assert_eq!(routed.len(), 1);
+        assert_eq!(routed[0].message.addr, "/devices/Hush/vibrate/speed");
+        assert_eq!(routed[0].message.args, Some(vec![OscType::Double(0.25)]));
+        let other = osc::Message { addr: String::from("/avatar/other"), args: Some(vec![OscType::Float(0.5)]) };
+        assert!(route(routes.iter(), &Transforms::default(), &other, "", false).is_empty());
+    }
+
+    #[test]
+    fn vrchat_ints_are_scaled_once() {
+        let route_config = |input: &str| toml::from_str::<RouteConfig>(&format!(
+            "match = \"/avatar/parameters/*\"\ndevice = \"Hush\"\ncommand = \"vibrate/speed\"\n{}", input,
+        )).expect("unexpected");
+        let received = osc::Message { addr: String::from("/avatar/parameters/Squeeze"), args: Some(vec![OscType::Int(51)]) };
+        for input in ["", "input = [0.0, 255.0]"].iter() {
+            let routes = [Route::new(route_config(input)).expect("unexpected")];
+            let routed = route(routes.iter(), &Transforms::default(), &received, "", true);
+            assert_eq!(routed[0].message.args, Some(vec![OscType::Double(0.2)]));
+        }
+    }
+
+    #[test]
+    fn runaway_transforms_fail() {
+        let routed = |transform: &str| {
+            let config: RouteConfig = toml::from_str(&format!(
+                "match = \"/a\"\ndevice = \"Hush\"\ncommand = \"vibrate/speed\"\ntransform = \"{}\"", transform,
+            )).expect("unexpected");
+            let routes = [Route::new(config).expect("unexpected")];
+            let received = osc::Message { addr: String::from("/a"), args: Some(vec![OscType::Float(0.5)]) };
+            route(routes.iter(), &Transforms::default(), &received, "", false).len()
+        };
+        let mean = |of: &str, count: usize| format!("[{}].reduce(|sum, i| sum + i, 0.0) / {}.0", vec![of; count].join(", "), count);
+        assert_eq!(routed(&mean("x", 3)), 1);
+        assert_eq!(routed(&mean("x", MAX_OPERATIONS as usize)), 0);
+        // constant, still limited
+        assert_eq!(routed(&mean("0.5", MAX_OPERATIONS as usize)), 0);
+    }
+
+    #[test]
+    fn deeply_nested_transforms_are_rejected() {
+        let transform = format!("{}x{}", "(".repeat(MAX_EXPR_DEPTH + 1), ")".repeat(MAX_EXPR_DEPTH + 1));
+        let config: RouteConfig = toml::from_str(&format!(
+            "match = \"/a\"\ndevice = \"Hush\"\ncommand = \"vibrate/speed\"\ntransform = \"{}\"", transform,
+        )).expect("unexpected");
+        assert!(Route::new(config).is_err());
+    }
+
+    #[test]
+    fn invalid_routes_are_rejected() {
+        let route = |extra: &str| Route::new(toml::from_str(
+            &format!("match = \"/a\"\ndevice = \"Hush\"\ncommand = \"vibrate/speed\"\n{}", extra),
+        ).expect("unexpected"));
+        assert!(route("deadzone = 0.1\nsmoothing = 0.5").is_ok());
+        assert!(route("deadzone = 1.0").is_err());
+        assert!(route("smoothing = -0.5").is_err());
+        assert!(route("input = [1.0, 1.0]").is_err());
+    }
+
+    #[test]
+    fn cancelled_smoothing_stops_its_task() {
+        let smoothing = Smoothing::default();
+        let message = message(1.0);
+        let task = smoothing.set_target(&message, 1.0).expect("unexpected");
+        assert_eq!(smoothing.set_target(&message, 0.5), None);
+        let (stepped, reached) = smoothing.step(&message.addr, task, 0.1, 1.0).expect("unexpected");
+        assert!(!reached && first_value(&stepped).is_some_and(|value| value > 0.0 && value < 0.5));
+        smoothing.cancel(&message.addr);
+        assert!(smoothing.step(&message.addr, task, 0.1, 1.0).is_none());
+        // approached from zero by another task
+        let restarted = smoothing.set_target(&message, 1.0).expect("unexpected");
+        assert!(smoothing.step(&message.addr, task, 0.1, 1.0).is_none());
+        let (stepped, _) = smoothing.step(&message.addr, restarted, 0.1, 1.0).expect("unexpected");
+        assert!(first_value(&stepped).is_some_and(|value| value < 0.1));
+    }
+
+    #[test]
+    fn cancelled_inputs_are_not_combined() {
+        let combiner = Combiner::default();
+        let combined = |received_addr: &str, value: f32| first_value(&combiner.combine(received_addr, message(value), Combine::Max));
+        assert_eq!(combined("/a", 0.75), Some(0.75));
+        assert_eq!(combined("/b", 0.25), Some(0.75));
+        combiner.cancel(&message(0.0).addr);
+        assert_eq!(combined("/b", 0.25), Some(0.25));
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use buttplug::client::ButtplugClientDeviceEvent;
+use buttplug::core::message::{ButtplugCurrentSpecServerMessage, SensorType};
+use futures_util::stream::StreamExt;
+use nannou_osc::rosc::OscType;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+use crate::{Device, FeatureIndex, normalize_device_name};
+use crate::osc_send::OscSender;
+
+/// Forwarders of the subscribed sensors, by device and sensor index
+#[derive(Default)]
+pub struct Sensors {
+    forwarders: Mutex<HashMap<(Device, u32), JoinHandle<()>>>,
+}
+
+/// By the name in the `sensor/<type>/<index>` addresses
+pub const SENSOR_TYPES: &[(&str, SensorType)] = &[
+    ("pressure", SensorType::Pressure),
+    ("button", SensorType::Button),
+    ("rssi", SensorType::RSSI),
+    ("battery", SensorType::Battery),
+];
+
+pub fn parse_sensor_type(s: &str) -> Result<SensorType, String> {
+    let name = s.to_lowercase();
+    SENSOR_TYPES.iter()
+        .find(|(sensor_name, _)| *sensor_name == name)
+        .map(|(_, sensor_type)| *sensor_type)
+        .ok_or_else(|| format!("invalid sensor type: {}", s))
+}
+
+/// Sensor index of the device for the index among the sensors of that type, as with scalar
+fn sensor_index(device: &Device, sensor_type: SensorType, index: FeatureIndex) -> Option<u32> {
+    device.message_attributes().sensor_subscribe_cmd().iter().flatten()
+        .enumerate()
+        .filter(|(_, attribute)| *attribute.sensor_type() == sensor_type)
+        .nth(index as usize)
+        .map(|(sensor_index, _)| sensor_index as u32)
+}
+
+impl Sensors {
+    /// Subscribes to the sensor, its readings are sent as `/devices/<name>/sensor/<type>/<index> <values>` via --osc-send
+    /// until unsubscribed or the device is removed
+    pub async fn subscribe(
+        self: Arc<Self>, device: Device, sensor_type: SensorType, index: FeatureIndex, osc_send: Arc<OscSender>,
+    ) -> Result<(), ()> {
+        let device_name = normalize_device_name(device.name());
+        let sensor_index = sensor_index(&device, sensor_type, index).ok_or_else(||
+            warn!("[{}] no {:?} sensor #{} to subscribe to", device_name, sensor_type, index)
+        )?;
+        let key = (device.clone(), sensor_index);
+        if self.forwarders.lock().expect("unexpected").contains_key(&key) {
+            debug!("[{}] already subscribed to {:?} #{}", device_name, sensor_type, index);
+            return Ok(());
+        }
+        // listening before subscribing, not to miss the first reading
+        let mut events = device.event_stream();
+        debug!("[{}] subscribing to {:?} #{}", device_name, sensor_type, index);
+        device.subscribe_sensor(sensor_index, sensor_type).await.map_err(|e|
+            error!("{:?}", e)
+        )?;
+        let address = format!("/devices/{}/sensor/{}/{}", device_name, format!("{:?}", sensor_type).to_lowercase(), index);
+        let sensors = self.clone();
+        let forwarder = tokio::task::spawn(async move {
+            while let Some(event) = events.next().await {
+                match event {
+                    ButtplugClientDeviceEvent::Message(ButtplugCurrentSpecServerMessage::SensorReading(reading))
+                        if reading.sensor_index() == sensor_index => {
+                        let args = reading.data().iter().map(|value| OscType::Int(*value)).collect();
+                        osc_send.send(&address, args);
+                    }
+                    ButtplugClientDeviceEvent::DeviceRemoved | ButtplugClientDeviceEvent::ClientDisconnect => break,
+                    _ => {}
+                }
+            }
+            sensors.forwarders.lock().expect("unexpected").remove(&(device, sensor_index));
+        });
+        if let Some(previous) = self.forwarders.lock().expect("unexpected").insert(key, forwarder) {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    pub async fn unsubscribe(&self, device: Device, sensor_type: SensorType, index: FeatureIndex) -> Result<(), ()> {
+        let device_name = normalize_device_name(device.name());
+        let sensor_index = sensor_index(&device, sensor_type, index).ok_or_else(||
+            warn!("[{}] no {:?} sensor #{} to unsubscribe from", device_name, sensor_type, index)
+        )?;
+        match self.forwarders.lock().expect("unexpected").remove(&(device.clone(), sensor_index)) {
+            Some(forwarder) => forwarder.abort(),
+            None => {
+                debug!("[{}] not subscribed to {:?} #{}", device_name, sensor_type, index);
+                return Ok(());
+            }
+        }
+        debug!("[{}] unsubscribing from {:?} #{}", device_name, sensor_type, index);
+        device.unsubscribe_sensor(sensor_index, sensor_type).await.map_err(|e|
+            error!("{:?}", e)
+        )
+    }
+}
@@ -0,0 +1,147 @@
+// https://datatracker.ietf.org/doc/html/rfc1055 as recommended by OSC 1.1 for stream transports
+
+use std::io::{self, BufReader, Read};
+use std::time::{Duration, Instant};
+use nannou_osc as osc;
+use url::Url;
+use tracing::{error, info, warn};
+use crate::OscHandler;
+use crate::intiface::reconnect_delay;
+use crate::metrics::METRICS;
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+const DEFAULT_BAUD_RATE: u32 = 115200;
+/// Longest delay between the attempts to (re)open the serial port, e.g. while the adapter is unplugged
+const REOPEN_MAX_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct SlipDecoder {
+    frame: Vec<u8>,
+    escaped: bool,
+}
+
+impl SlipDecoder {
+    /// Returns the complete frame once its END byte is received
+    fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        if self.escaped {
+            self.escaped = false;
+            match byte {
+                SLIP_ESC_END => self.frame.push(SLIP_END),
+                SLIP_ESC_ESC => self.frame.push(SLIP_ESC),
+                _ => self.frame.push(byte),
+            }
+            return None;
+        }
+        match byte {
+            SLIP_END if self.frame.is_empty() => None,
+            SLIP_END => Some(std::mem::take(&mut self.frame)),
+            SLIP_ESC => {
+                self.escaped = true;
+                None
+            }
+            _ => {
+                self.frame.push(byte);
+                None
+            }
+        }
+    }
+}
+
+/// serial:///dev/ttyUSB0?baud=115200 or serial:///COM3?baud=115200
+pub fn validate_serial_url(serial_url: &Url) -> (String, u32) {
+    let path = serial_url.path();
+    let path = if cfg!(windows) { path.trim_start_matches('/') } else { path };
+    if path.is_empty() {
+        panic!("Invalid --osc-listen: serial port path is missing");
+    }
+    let baud_rate = serial_url.query_pairs()
+        .find(|(k, _)| k == "baud")
+        .map(|(_, v)| v.parse().expect("Invalid --osc-listen: baud must be a number"))
+        .unwrap_or(DEFAULT_BAUD_RATE);
+    (String::from(path), baud_rate)
+}
+
+/// Reopens the serial port with a backoff whenever it fails, e.g. once the adapter is plugged back in
+pub fn serial_listen(path: &str, baud_rate: u32, osc_handler: OscHandler) {
+    let mut failures = 0;
+    loop {
+        match serialport::new(path, baud_rate).timeout(Duration::from_secs(60)).open() {
+            Ok(port) => {
+                if failures > 0 {
+                    info!("[{}] reopened", path);
+                }
+                failures = 0;
+                METRICS.listener("osc", true);
+                read_port(path, port, &osc_handler);
+                METRICS.listener("osc", false);
+            }
+            Err(e) => error!("[{}] couldn't open serial port: {:?}", path, e),
+        }
+        failures += 1;
+        let delay = reconnect_delay(failures, REOPEN_MAX_INTERVAL);
+        warn!("[{}] reopening in {:.1}s", path, delay.as_secs_f64());
+        std::thread::sleep(delay);
+    }
+}
+
+/// Until the port fails
+fn read_port(path: &str, port: Box<dyn serialport::SerialPort>, osc_handler: &OscHandler) {
+    let mut decoder = SlipDecoder::default();
+    for byte in BufReader::new(port).bytes() {
+        match byte {
+            Ok(byte) => {
+                if let Some(frame) = decoder.push(byte) {
+                    let received = Instant::now();
+                    match osc::rosc::decoder::decode(&frame) {
+                        Ok(packet) => {
+                            METRICS.source(path);
+                            osc_handler.receive_packet(osc::Packet::from(packet), path, received);
+                        }
+                        Err(e) => warn!("[{}] invalid packet: {:?}", path, e),
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => {
+                error!("[{}] {:?}", path, e);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut decoder = SlipDecoder::default();
+        bytes.iter().filter_map(|byte| decoder.push(*byte)).collect()
+    }
+
+    #[test]
+    fn frames_end_with_end_byte() {
+        assert_eq!(decode(&[1, 2, SLIP_END, 3, SLIP_END]), vec![vec![1, 2], vec![3]]);
+        assert_eq!(decode(&[1, 2]), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn empty_frames_are_skipped() {
+        // END is also sent before a frame, to flush the line noise
+        assert_eq!(decode(&[SLIP_END, SLIP_END, 1, SLIP_END, SLIP_END]), vec![vec![1]]);
+    }
+
+    #[test]
+    fn escaped_bytes_are_restored() {
+        assert_eq!(
+            decode(&[SLIP_ESC, SLIP_ESC_END, 1, SLIP_ESC, SLIP_ESC_ESC, SLIP_END]),
+            vec![vec![SLIP_END, 1, SLIP_ESC]],
+        );
+        // invalid escape, kept as is
+        assert_eq!(decode(&[SLIP_ESC, 2, SLIP_END]), vec![vec![2]]);
+    }
+}
@@ -0,0 +1,108 @@
+// https://learn.microsoft.com/en-us/windows/win32/services/services
+
+use std::ffi::OsString;
+use std::time::Duration;
+use anyhow::Result;
+use structopt::StructOpt;
+use tokio::sync::watch;
+use tracing::error;
+use windows_service::{define_windows_service, service_control_handler, service_dispatcher};
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo,
+    ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::ServiceControlHandlerResult;
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use crate::CliArgs;
+
+const SERVICE_NAME: &str = "buttplug-osc";
+
+/// Registers the service starting at boot with the same arguments, except for --service
+pub fn install() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
+    let mut launch_arguments = Vec::new();
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--service" {
+            args.next();
+        } else if !arg.to_string_lossy().starts_with("--service=") {
+            launch_arguments.push(arg);
+        }
+    }
+    launch_arguments.push(OsString::from("--service"));
+    launch_arguments.push(OsString::from("run"));
+    let service = manager.create_service(&ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("buttplug-osc"),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments,
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    }, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("Control https://buttplug.io/ devices via OSC")?;
+    println!("Installed the {} service, start it with `sc start {}` or reboot", SERVICE_NAME, SERVICE_NAME);
+    Ok(())
+}
+
+/// Stops the service if it's running, and removes it
+pub fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    service.delete()?;
+    println!("Uninstalled the {} service", SERVICE_NAME);
+    Ok(())
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Runs the bridge as the service started by the service control manager
+pub fn run() -> Result<()> {
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("[Service] {:?}", e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let (stop_tx, mut stop_rx) = watch::channel(false);
+    let status_handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            let _ = stop_tx.send(true);
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+    let status = |current_state, controls_accepted| ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(0),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    };
+    status_handle.set_service_status(status(ServiceState::Running, ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN))?;
+    // the process arguments, as registered by install()
+    let matches = CliArgs::clap().get_matches();
+    let args = CliArgs::from_clap(&matches);
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(crate::run(matches, args, async move {
+        let _ = stop_rx.changed().await;
+    }));
+    // the receivers block forever
+    runtime.shutdown_timeout(Duration::from_secs(1));
+    status_handle.set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty()))?;
+    result
+}
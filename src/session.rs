@@ -0,0 +1,95 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use nannou_osc::rosc::OscType;
+use tracing::{debug, error, warn};
+use crate::{Millis, OscHandler, normalize_device_name, patterns, unique_devices};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the devices take to fade out when the session ends
+const FADE_MILLIS: Millis = 5000;
+
+/// Time limit of the session (--session-limit), after which the commands are ignored until /session/reset
+pub struct Session {
+    limit: Duration,
+    started: Mutex<Instant>,
+    ended: AtomicBool,
+}
+
+impl Session {
+    pub fn new(limit: Duration) -> Self {
+        Session { limit, started: Mutex::new(Instant::now()), ended: AtomicBool::new(false) }
+    }
+
+    pub fn ended(&self) -> bool {
+        self.ended.load(Ordering::SeqCst)
+    }
+
+    /// Starts a new session
+    pub fn reset(&self) {
+        *self.started.lock().expect("unexpected") = Instant::now();
+        self.ended.store(false, Ordering::SeqCst);
+    }
+
+    /// Ends the session once it exceeds the limit, true only when it has just ended
+    fn end_if_expired(&self) -> bool {
+        if self.ended() || self.started.lock().expect("unexpected").elapsed() < self.limit {
+            return false;
+        }
+        !self.ended.swap(true, Ordering::SeqCst)
+    }
+}
+
+/// Ends the session once it exceeds the limit: fades out and stops every device, sending `/status/session True`
+pub async fn watch(osc_handler: OscHandler) {
+    let session = match osc_handler.session {
+        Some(ref session) => session.clone(),
+        None => return,
+    };
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if !session.end_if_expired() {
+            continue;
+        }
+        warn!("Session limit of {} minutes reached: fading out all devices and ignoring the commands until /session/reset",
+            session.limit.as_secs() / 60);
+        osc_handler.osc_send.send("/status/session", vec![OscType::Bool(true)]);
+        for device in unique_devices(&osc_handler.devices) {
+            let device_name = normalize_device_name(device.name());
+            let states = osc_handler.states.clone();
+            states.set_baseline(&device_name, 0.0);
+            let fade = {
+                let (states, device_name, tick_rate) = (states.clone(), device_name.clone(), osc_handler.tick_rate);
+                async move {
+                    patterns::ramp(device.clone(), states.clone(), 0.0, FADE_MILLIS, tick_rate).await;
+                    states.reset(&device_name);
+                    debug!("[{}] stopping", device_name);
+                    if let Err(e) = device.stop().await {
+                        error!("{:?}", e);
+                    }
+                }
+            };
+            states.set_task(&device_name, tokio::spawn(fade));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ended_until_reset() {
+        let session = Session::new(Duration::from_millis(20));
+        assert!(!session.end_if_expired());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(session.end_if_expired());
+        // latched, ended only once
+        assert!(!session.end_if_expired());
+        assert!(session.ended());
+        session.reset();
+        assert!(!session.ended());
+        assert!(!session.end_if_expired());
+    }
+}
@@ -0,0 +1,220 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use serde::Deserialize;
+use crate::{Speed, FeatureIndex};
+use crate::config::Config;
+
+/// Response curve from the requested speed to the one sent to the device
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Curve {
+    /// `speed ^ gamma`
+    Gamma(f64),
+    /// Points `[requested, sent]` interpolated linearly
+    Points(Vec<(Speed, Speed)>),
+}
+
+impl Curve {
+    /// Positive gamma, at least one point
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Curve::Gamma(gamma) if !gamma.is_finite() || *gamma <= 0.0 => Err(String::from("gamma must be positive")),
+            Curve::Points(points) if points.is_empty() => Err(String::from("no points")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Points ordered by the requested speed
+    fn sorted(mut self) -> Self {
+        if let Curve::Points(ref mut points) = self {
+            points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        }
+        self
+    }
+
+    fn apply(&self, speed: Speed) -> Speed {
+        match self {
+            Curve::Gamma(gamma) => speed.max(0.0).powf(*gamma),
+            Curve::Points(points) => {
+                match (points.first(), points.last()) {
+                    (Some(first), _) if speed <= first.0 => first.1,
+                    (_, Some(last)) if speed >= last.0 => last.1,
+                    _ => match points.iter().position(|point| point.0 > speed) {
+                        Some(i) if i > 0 => {
+                            let (a, b) = (points[i - 1], points[i]);
+                            a.1 + (b.1 - a.1) * (speed - a.0) / (b.0 - a.0)
+                        }
+                        _ => speed,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// How the requested vibration speeds are turned into the ones sent to each device
+pub struct Shaping {
+    devices: HashMap<String, DeviceShaping>,
+    default: DeviceShaping,
+}
+
+struct DeviceShaping {
+    curve: Option<Curve>,
+    min_speed: Speed,
+    max_speed: Speed,
+    slew_rate: Option<f64>,
+    motors: HashMap<FeatureIndex, FeatureIndex>,
+}
+
+impl Default for Shaping {
+    fn default() -> Self {
+        Shaping::from_config(&Config::default()).expect("unexpected")
+    }
+}
+
+impl Shaping {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let default = DeviceShaping {
+            curve: None,
+            min_speed: config.min_speed.unwrap_or(0.0),
+            max_speed: config.max_speed.unwrap_or(1.0),
+            slew_rate: config.slew_rate,
+            motors: HashMap::new(),
+        };
+        check_speeds("", default.min_speed, default.max_speed)?;
+        check_slew_rate("slew_rate", default.slew_rate)?;
+        let mut devices = HashMap::new();
+        for (name, device) in config.device.iter() {
+            check_slew_rate(&format!("device.{}.slew_rate", name), device.slew_rate)?;
+            let mut motors = HashMap::new();
+            for (from, to) in device.motors.iter() {
+                match from.parse() {
+                    Ok(from) => motors.insert(from, *to),
+                    Err(_) => return Err(format!("device.{}.motors: invalid motor index {}", name, from)),
+                };
+            }
+            if let Some(Err(e)) = device.curve.as_ref().map(Curve::validate) {
+                return Err(format!("device.{}.curve: {}", name, e));
+            }
+            let (min_speed, max_speed) = (device.min_speed.unwrap_or(default.min_speed), device.max_speed.unwrap_or(default.max_speed));
+            check_speeds(&format!("device.{}.", name), min_speed, max_speed)?;
+            devices.insert(name.clone(), DeviceShaping {
+                curve: device.curve.clone().map(Curve::sorted),
+                min_speed,
+                max_speed,
+                slew_rate: device.slew_rate.or(default.slew_rate),
+                motors,
+            });
+        }
+        Ok(Shaping { devices, default })
+    }
+
+    /// Motor index of the device for the one in the OSC message
+    pub fn motor(&self, device_name: &str, index: FeatureIndex) -> FeatureIndex {
+        let shaping = self.devices.get(device_name).unwrap_or(&self.default);
+        shaping.motors.get(&index).cloned().unwrap_or(index)
+    }
+
+    /// Largest change of the requested vibration speed per second, if limited
+    pub fn slew_rate(&self, device_name: &str) -> Option<f64> {
+        self.devices.get(device_name).unwrap_or(&self.default).slew_rate
+    }
+
+    /// Curve, then clamping of non-zero speeds, so that zero still stops the device
+    pub fn apply(&self, device_name: &str, speed: Speed) -> Speed {
+        if speed <= 0.0 {
+            return 0.0;
+        }
+        let shaping = self.devices.get(device_name).unwrap_or(&self.default);
+        let speed = match shaping.curve {
+            Some(ref curve) => curve.apply(speed),
+            None => speed,
+        };
+        if speed > 0.0 {
+            speed.max(shaping.min_speed).min(shaping.max_speed)
+        } else {
+            0.0
+        }
+    }
+}
+
+fn check_speeds(prefix: &str, min_speed: Speed, max_speed: Speed) -> Result<(), String> {
+    if !(0.0..=1.0).contains(&min_speed) || !(0.0..=1.0).contains(&max_speed) || min_speed > max_speed {
+        return Err(format!("{}min_speed, {}max_speed: must be within 0..1, in order", prefix, prefix));
+    }
+    Ok(())
+}
+
+/// Zero or negative rates would never reach the requested speed
+fn check_slew_rate(key: &str, slew_rate: Option<f64>) -> Result<(), String> {
+    match slew_rate {
+        Some(slew_rate) if !slew_rate.is_finite() || slew_rate <= 0.0 => Err(format!("{}: must be positive", key)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shaping(config: &str) -> Result<Shaping, String> {
+        Shaping::from_config(&toml::from_str(config).expect("unexpected"))
+    }
+
+    fn close(a: Speed, b: Speed) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn gamma_curve() {
+        let curve = Curve::Gamma(2.0);
+        assert!(close(curve.apply(0.5), 0.25));
+        assert!(close(curve.apply(1.0), 1.0));
+    }
+
+    #[test]
+    fn points_are_interpolated_in_order() {
+        let curve = Curve::Points(vec![(1.0, 1.0), (0.0, 0.1), (0.5, 0.3)]).sorted();
+        assert!(close(curve.apply(0.25), 0.2));
+        assert!(close(curve.apply(0.75), 0.65));
+        // held at the ends
+        assert!(close(curve.apply(-1.0), 0.1));
+        assert!(close(curve.apply(2.0), 1.0));
+    }
+
+    #[test]
+    fn zero_stops_regardless_of_the_curve_and_clamps() {
+        let shaping = shaping("[device.Hush]\ncurve = [[0.0, 0.2], [1.0, 1.0]]\nmin_speed = 0.3\nmax_speed = 0.8")
+            .expect("unexpected");
+        assert_eq!(shaping.apply("Hush", 0.0), 0.0);
+        assert_eq!(shaping.apply("Hush", -0.5), 0.0);
+        assert!(close(shaping.apply("Hush", 0.01), 0.3));
+        assert!(close(shaping.apply("Hush", 1.0), 0.8));
+        // others take the defaults
+        assert!(close(shaping.apply("Edge", 0.01), 0.01));
+    }
+
+    #[test]
+    fn invalid_curves_are_rejected() {
+        assert!(shaping("[device.Hush]\ncurve = 0.0").is_err());
+        assert!(shaping("[device.Hush]\ncurve = -2.0").is_err());
+        assert!(shaping("[device.Hush]\ncurve = []").is_err());
+        assert!(shaping("[device.Hush]\ncurve = 2.2").is_ok());
+    }
+
+    #[test]
+    fn invalid_speeds_are_rejected() {
+        assert!(shaping("min_speed = 0.5\nmax_speed = 0.4").is_err());
+        assert!(shaping("max_speed = 1.5").is_err());
+        assert!(shaping("[device.Hush]\nmin_speed = -0.1").is_err());
+        assert!(shaping("slew_rate = 0.0").is_err());
+    }
+
+    #[test]
+    fn motors_are_remapped() {
+        let shaping = shaping("[device.Edge]\nmotors = { 0 = 1, 1 = 0 }").expect("unexpected");
+        assert_eq!(shaping.motor("Edge", 0), 1);
+        assert_eq!(shaping.motor("Edge", 2), 2);
+        assert_eq!(shaping.motor("Hush", 0), 0);
+    }
+}
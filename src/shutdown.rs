@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{debug, error, info};
+use crate::intiface::Clients;
+use crate::state::DeviceStates;
+
+/// How long a panic waits for the devices to stop before aborting
+const PANIC_STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+static PANICKED: AtomicBool = AtomicBool::new(false);
+
+/// Resolves on SIGINT or SIGTERM; on Windows, on Ctrl+C
+#[cfg(unix)]
+pub async fn signal() {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("couldn't listen for SIGTERM");
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result.expect("couldn't listen for SIGINT"),
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn signal() {
+    tokio::signal::ctrl_c().await.expect("couldn't listen for Ctrl+C");
+}
+
+/// Stops every device, so none keeps running at the last speed, and disconnects from the Intiface servers
+pub async fn stop(clients: &Clients, states: &DeviceStates) {
+    info!("Shutting down");
+    states.reset_all();
+    let clients = clients.lock().expect("unexpected").clone();
+    for client in clients {
+        debug!("stopping all devices");
+        if let Err(e) = client.stop_all_devices().await {
+            error!("{:?}", e);
+        }
+        let _ = client.disconnect().await;
+    }
+}
+
+/// On any panic, including in the spawned tasks and receiver threads, stops every device and aborts,
+/// so a crash never leaves them running at the last speed
+pub fn stop_on_panic(clients: Clients, states: DeviceStates) {
+    let runtime = tokio::runtime::Handle::current();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        // e.g. a poisoned lock while stopping
+        if PANICKED.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        error!("{}, stopping all devices", info);
+        let (runtime, clients, states) = (runtime.clone(), clients.clone(), states.clone());
+        // can't block a thread of the runtime on it
+        let stopping = std::thread::spawn(move || {
+            runtime.block_on(async {
+                let _ = tokio::time::timeout(PANIC_STOP_TIMEOUT, stop(&clients, &states)).await;
+            });
+        });
+        let _ = stopping.join();
+        std::process::abort();
+    }));
+}
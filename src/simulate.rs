@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Debug};
+use std::str::FromStr;
+use std::sync::Arc;
+use buttplug::client::ButtplugClientDeviceEvent;
+use buttplug::client::device::{ScalarValueCommand, ScalarCommand, RotateCommand, LinearCommand};
+use buttplug::core::errors::{ButtplugDeviceError, ButtplugError};
+use buttplug::core::message::{
+    ActuatorType, ButtplugDeviceMessageType, ClientDeviceMessageAttributes, ClientDeviceMessageAttributesBuilder,
+    ClientGenericDeviceMessageAttributes, Endpoint, SensorType,
+};
+use futures_util::future::{self, FutureExt};
+use futures_util::stream::{self, Stream};
+use nannou_osc::rosc::OscType;
+use tracing::info;
+use crate::normalize_device_name;
+use crate::device::{DeviceBackend, DeviceResultFuture};
+use crate::osc_send::OscSender;
+
+/// Number of steps of the simulated actuators, as of the most of the real ones
+const STEP_COUNT: u32 = 20;
+
+/// Fake device from `--simulate-device <name>:<vibrators>:<linear>`
+#[derive(Debug)]
+pub struct SimulatedDevice {
+    pub name: String,
+    vibrators: u32,
+    linear: u32,
+}
+
+impl FromStr for SimulatedDevice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts[..] {
+            [name, vibrators, linear] if !normalize_device_name(name).is_empty() => Ok(SimulatedDevice {
+                name: normalize_device_name(name),
+                vibrators: vibrators.parse().map_err(|_| String::from("<vibrators> must be a number"))?,
+                linear: linear.parse().map_err(|_| String::from("<linear> must be a number"))?,
+            }),
+            _ => Err(String::from("expected <name>:<vibrators>:<linear>")),
+        }
+    }
+}
+
+/// Err if several --simulate-device have the same name: only one of them would be addressable
+pub fn check_names(devices: &[SimulatedDevice]) -> Result<(), String> {
+    let mut names = HashSet::new();
+    match devices.iter().find(|device| !names.insert(device.name.as_str())) {
+        Some(device) => Err(format!("{}: name taken by another simulated device", device.name)),
+        None => Ok(()),
+    }
+}
+
+impl SimulatedDevice {
+    /// Registered among the devices of the servers with the index, sending what it receives via --osc-send
+    pub fn backend(&self, index: u32, osc_send: Arc<OscSender>) -> Simulated {
+        let actuators = |count: u32, actuator_type: ActuatorType| (0..count)
+            .map(|_| ClientGenericDeviceMessageAttributes::new("Simulated", STEP_COUNT, actuator_type))
+            .collect::<Vec<_>>();
+        let mut attributes = ClientDeviceMessageAttributesBuilder::default();
+        if self.vibrators > 0 {
+            attributes.scalar_cmd(&actuators(self.vibrators, ActuatorType::Vibrate));
+        }
+        if self.linear > 0 {
+            attributes.linear_cmd(&actuators(self.linear, ActuatorType::Position));
+        }
+        Simulated {
+            name: self.name.clone(),
+            index,
+            vibrators: self.vibrators,
+            linear: self.linear,
+            attributes: attributes.finish(),
+            osc_send,
+        }
+    }
+}
+
+/// --simulate-device among the devices: logs the commands it receives, as sent to a real device
+/// after the shaping, and echoes them via --osc-send as `/devices/<name>/simulated <command>`
+pub struct Simulated {
+    name: String,
+    index: u32,
+    vibrators: u32,
+    linear: u32,
+    attributes: ClientDeviceMessageAttributes,
+    osc_send: Arc<OscSender>,
+}
+
+impl Debug for Simulated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Simulated").field("name", &self.name).field("index", &self.index).finish()
+    }
+}
+
+impl Simulated {
+    /// Logs the command if the actuators it addresses are simulated
+    fn simulate(&self, command: String, actuators: Option<u32>, count: u32, message_type: ButtplugDeviceMessageType) -> DeviceResultFuture {
+        let error = match actuators {
+            _ if count == 0 => Some(ButtplugDeviceError::MessageNotSupported(message_type)),
+            Some(actuators) if actuators > count => Some(ButtplugDeviceError::DeviceFeatureCountMismatch(count, actuators)),
+            _ => None,
+        };
+        if let Some(error) = error {
+            return unsupported(error);
+        }
+        info!("[{}] simulated {}", self.name, command);
+        self.osc_send.send(&format!("/devices/{}/simulated", self.name), vec![OscType::String(command)]);
+        future::ready(Ok(())).boxed()
+    }
+}
+
+fn unsupported<T: Send + 'static>(error: ButtplugDeviceError) -> DeviceResultFuture<T> {
+    future::ready(Err(ButtplugError::from(error).into())).boxed()
+}
+
+/// By index, for the log
+fn sorted<T>(map: &HashMap<u32, T>) -> BTreeMap<&u32, &T> {
+    map.iter().collect()
+}
+
+/// Number of the actuators addressed by the indexes: up to the highest one
+fn addressed<'a>(indexes: impl Iterator<Item=&'a u32>) -> Option<u32> {
+    Some(indexes.max().map_or(0, |index| index + 1))
+}
+
+impl DeviceBackend for Simulated {
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    fn index(&self) -> u32 {
+        self.index
+    }
+
+    fn message_attributes(&self) -> &ClientDeviceMessageAttributes {
+        &self.attributes
+    }
+
+    fn vibrate(&self, command: &ScalarValueCommand) -> DeviceResultFuture {
+        let (actuators, speeds) = match command {
+            ScalarValueCommand::ScalarValue(speed) => (None, format!("{}", speed)),
+            ScalarValueCommand::ScalarValueVec(speeds) => (Some(speeds.len() as u32), format!("{:?}", speeds)),
+            ScalarValueCommand::ScalarValueMap(speeds) => (addressed(speeds.keys()), format!("{:?}", sorted(speeds))),
+        };
+        self.simulate(format!("Vibrate({})", speeds), actuators, self.vibrators, ButtplugDeviceMessageType::ScalarCmd)
+    }
+
+    fn scalar(&self, command: &ScalarCommand) -> DeviceResultFuture {
+        let (actuators, types, values) = match command {
+            ScalarCommand::Scalar(value) => (None, vec![value.1], format!("{:?}", value)),
+            ScalarCommand::ScalarVec(values) => (
+                Some(values.len() as u32), values.iter().map(|(_, t)| *t).collect(), format!("{:?}", values),
+            ),
+            ScalarCommand::ScalarMap(values) => (
+                addressed(values.keys()), values.values().map(|(_, t)| *t).collect(), format!("{:?}", sorted(values)),
+            ),
+        };
+        // the simulated scalar actuators only vibrate
+        let count = if types.iter().all(|t| *t == ActuatorType::Vibrate) { self.vibrators } else { 0 };
+        self.simulate(format!("Scalar({})", values), actuators, count, ButtplugDeviceMessageType::ScalarCmd)
+    }
+
+    fn rotate(&self, _: &RotateCommand) -> DeviceResultFuture {
+        unsupported(ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::RotateCmd))
+    }
+
+    fn linear(&self, command: &LinearCommand) -> DeviceResultFuture {
+        let (actuators, moves) = match command {
+            LinearCommand::Linear(duration, position) => (None, format!("{}, {}", position, duration)),
+            LinearCommand::LinearVec(moves) => (Some(moves.len() as u32), format!("{:?}", moves)),
+            LinearCommand::LinearMap(moves) => (addressed(moves.keys()), format!("{:?}", sorted(moves))),
+        };
+        self.simulate(format!("Linear({})", moves), actuators, self.linear, ButtplugDeviceMessageType::LinearCmd)
+    }
+
+    fn stop(&self) -> DeviceResultFuture {
+        // every device can stop
+        self.simulate(String::from("Stop"), None, 1, ButtplugDeviceMessageType::StopDeviceCmd)
+    }
+
+    fn has_battery_level(&self) -> bool {
+        false
+    }
+
+    fn battery_level(&self) -> DeviceResultFuture<f64> {
+        unsupported(ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::SensorReadCmd))
+    }
+
+    fn raw_write(&self, _: Endpoint, _: &[u8], _: bool) -> DeviceResultFuture {
+        unsupported(ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::RawWriteCmd))
+    }
+
+    fn event_stream(&self) -> Box<dyn Stream<Item = ButtplugClientDeviceEvent> + Send + Unpin> {
+        Box::new(stream::pending())
+    }
+
+    fn subscribe_sensor(&self, _: u32, _: SensorType) -> DeviceResultFuture {
+        unsupported(ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::SensorSubscribeCmd))
+    }
+
+    fn unsubscribe_sensor(&self, _: u32, _: SensorType) -> DeviceResultFuture {
+        unsupported(ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::SensorUnsubscribeCmd))
+    }
+}
@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{info, warn, error};
+
+/// Delay before starting the exited Intiface Engine again
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+
+/// Runs Intiface Engine from --spawn-intiface, starting it again whenever it exits;
+/// it's killed along with this task when the program exits
+pub async fn supervise(path: PathBuf, websocket_port: u16, extra_args: Vec<String>) {
+    loop {
+        info!("Starting Intiface Engine ({}, port {})", path.display(), websocket_port);
+        let child = Command::new(&path)
+            .arg("--websocket-port").arg(websocket_port.to_string())
+            .arg("--use-bluetooth-le")
+            .args(&extra_args)
+            .kill_on_drop(true)
+            .spawn();
+        match child {
+            Ok(mut child) => match child.wait().await {
+                Ok(status) => warn!("Intiface Engine exited ({})", status),
+                Err(e) => error!("{:?}", e),
+            },
+            Err(e) => error!("Couldn't start Intiface Engine ({}): {:?}", path.display(), e),
+        }
+        tokio::time::sleep(RESTART_DELAY).await;
+    }
+}
@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio::time;
+use buttplug::client::device::ScalarValueCommand;
+use tracing::{debug, error};
+use crate::{Device, Speed, FeatureIndex, normalize_device_name};
+use crate::shaping::Shaping;
+
+/// What the bridge has commanded to each device, by normalized device name
+#[derive(Clone)]
+pub struct DeviceStates {
+    states: Arc<Mutex<HashMap<String, DeviceState>>>,
+    shaping: Arc<RwLock<Shaping>>,
+    /// Ceiling of every sent speed, after the shaping (--max-intensity)
+    max_intensity: Option<Speed>,
+    /// Latched by /estop until /estop/reset, nothing is sent to the devices meanwhile
+    estop: Arc<AtomicBool>,
+    /// Duration of the ramp up of the first vibration after a device (re)appears (--soft-start)
+    soft_start: Option<Duration>,
+    /// How many times per second the speeds limited by the slew_rate are sent (--tick-rate)
+    tick_rate: f64,
+}
+
+#[derive(Default)]
+struct DeviceState {
+    /// Vibration speed currently sent to the device
+    speed: Speed,
+    /// Vibration speed requested by the latest command, reached at the slew_rate
+    target: Speed,
+    /// Vibration speeds of the motors set apart from the whole device, requested and currently sent
+    motors: HashMap<FeatureIndex, (Speed, Speed)>,
+    /// Background task sending the speeds towards the requested ones, while limited by the slew_rate
+    sender: Option<JoinHandle<()>>,
+    /// Vibration speed set by the last lasting command, temporary ones (e.g. pulse) return to it
+    baseline: Speed,
+    /// Background task (e.g. pulse timer) driving the device until the next command
+    task: Option<JoinHandle<()>>,
+    /// When the latest command was received, until the device is stopped, for --watchdog
+    commanded: Option<Instant>,
+    /// Whether the next non-zero vibration starts the ramp up over --soft-start
+    soft_start: bool,
+    /// When the ramp up over --soft-start started, until it ends
+    soft_start_since: Option<Instant>,
+}
+
+impl DeviceStates {
+    pub fn new(shaping: Shaping, max_intensity: Option<Speed>, soft_start: Option<Duration>, tick_rate: f64) -> Self {
+        DeviceStates {
+            states: Default::default(),
+            shaping: Arc::new(RwLock::new(shaping)),
+            max_intensity,
+            estop: Arc::default(),
+            soft_start,
+            tick_rate,
+        }
+    }
+
+    pub fn estop(&self) -> bool {
+        self.estop.load(Ordering::SeqCst)
+    }
+
+    pub fn set_estop(&self, estop: bool) {
+        self.estop.store(estop, Ordering::SeqCst);
+    }
+
+    /// Speed to send to the device for the requested one
+    pub fn shape(&self, name: &str, speed: Speed) -> Speed {
+        self.cap(self.shaping.read().expect("unexpected").apply(name, speed))
+    }
+
+    /// Limits the speed (or other intensity, e.g. of rotation) to --max-intensity
+    pub fn cap(&self, speed: Speed) -> Speed {
+        self.max_intensity.map_or(speed, |max_intensity| speed.min(max_intensity))
+    }
+
+    /// Applies to the speeds sent from now on
+    pub fn set_shaping(&self, shaping: Shaping) {
+        *self.shaping.write().expect("unexpected") = shaping;
+    }
+
+    /// Largest change of the vibration speed per tick, if limited
+    fn slew_step(&self, name: &str) -> Option<Speed> {
+        self.shaping.read().expect("unexpected").slew_rate(name).map(|rate| rate / self.tick_rate)
+    }
+
+    /// Motor index of the device for the requested one
+    pub fn motor(&self, name: &str, index: FeatureIndex) -> FeatureIndex {
+        self.shaping.read().expect("unexpected").motor(name, index)
+    }
+
+    fn with<R>(&self, name: &str, f: impl FnOnce(&mut DeviceState) -> R) -> R {
+        let mut states = self.states.lock().expect("unexpected");
+        f(states.entry(String::from(name)).or_default())
+    }
+
+    pub fn speed(&self, name: &str) -> Speed {
+        self.with(name, |state| state.speed)
+    }
+
+    pub fn baseline(&self, name: &str) -> Speed {
+        self.with(name, |state| state.baseline)
+    }
+
+    pub fn set_baseline(&self, name: &str, speed: Speed) {
+        self.with(name, |state| state.baseline = speed)
+    }
+
+    /// Aborts the background task driving the device, if any
+    pub fn cancel_task(&self, name: &str) {
+        if let Some(task) = self.with(name, |state| state.task.take()) {
+            task.abort();
+        }
+    }
+
+    /// Forgets the speeds, e.g. when the device is stopped
+    pub fn reset(&self, name: &str) {
+        self.with(name, DeviceState::reset)
+    }
+
+    /// The device (re)appeared, e.g. after a reconnect, not vibrating
+    pub fn added(&self, name: &str) {
+        let soft_start = self.soft_start.is_some();
+        self.with(name, |state| {
+            state.speed = 0.0;
+            state.soft_start = soft_start;
+            state.soft_start_since = None;
+        })
+    }
+
+    /// The device received a command, for --watchdog
+    pub fn commanded(&self, name: &str) {
+        self.with(name, |state| state.commanded = Some(Instant::now()))
+    }
+
+    /// Fraction of the requested vibration speeds sent while ramping up over --soft-start,
+    /// which starts with the first non-zero vibration since the device (re)appeared
+    fn envelope(&self, state: &mut DeviceState) -> Option<Speed> {
+        if state.vibrating() {
+            state.start_soft_start();
+        }
+        let (since, duration) = (state.soft_start_since?, self.soft_start?);
+        let envelope = since.elapsed().as_secs_f64() / duration.as_secs_f64();
+        if envelope < 1.0 {
+            Some(envelope)
+        } else {
+            state.soft_start_since = None;
+            None
+        }
+    }
+
+    /// Devices not commanded within the window since their latest command, forgetting it
+    pub fn expired(&self, window: Duration) -> Vec<String> {
+        let mut states = self.states.lock().expect("unexpected");
+        states.iter_mut()
+            .filter(|(_, state)| state.commanded.is_some_and(|commanded| commanded.elapsed() >= window))
+            .map(|(name, state)| {
+                state.commanded = None;
+                name.clone()
+            })
+            .collect()
+    }
+
+    /// Aborts every background task and forgets the speeds, e.g. when all devices are stopped
+    pub fn reset_all(&self) {
+        let mut states = self.states.lock().expect("unexpected");
+        for state in states.values_mut() {
+            if let Some(task) = state.task.take() {
+                task.abort();
+            }
+            state.reset();
+        }
+    }
+
+    /// Replaces the background task driving the device
+    pub fn set_task(&self, name: &str, task: JoinHandle<()>) {
+        if let Some(previous) = self.with(name, |state| state.task.replace(task)) {
+            previous.abort();
+        }
+    }
+}
+
+impl DeviceState {
+    fn reset(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            sender.abort();
+        }
+        self.speed = 0.0;
+        self.target = 0.0;
+        self.motors.clear();
+        self.baseline = 0.0;
+        self.commanded = None;
+        // an unfinished ramp up starts over with the next vibration
+        if self.soft_start_since.take().is_some() {
+            self.soft_start = true;
+        }
+    }
+
+    fn vibrating(&self) -> bool {
+        self.target > 0.0 || self.motors.values().any(|(target, _)| *target > 0.0)
+    }
+
+    fn start_soft_start(&mut self) {
+        if std::mem::take(&mut self.soft_start) {
+            self.soft_start_since = Some(Instant::now());
+        }
+    }
+
+    /// Moves the sent speeds towards the requested ones (scaled by the envelope) by at most the step,
+    /// returning the whole device speed and the motor speeds which changed
+    fn advance(&mut self, step: Option<Speed>, envelope: Option<Speed>) -> (Option<Speed>, Vec<(FeatureIndex, Speed)>) {
+        let envelope = envelope.unwrap_or(1.0);
+        let speed = towards(self.speed, self.target * envelope, step);
+        let changed = if speed != self.speed { Some(speed) } else { None };
+        self.speed = speed;
+        let mut motors = Vec::new();
+        for (index, (target, speed)) in self.motors.iter_mut() {
+            let next = towards(*speed, *target * envelope, step);
+            if next != *speed {
+                *speed = next;
+                motors.push((*index, next));
+            }
+        }
+        (changed, motors)
+    }
+
+    fn reached(&self) -> bool {
+        self.speed == self.target && self.motors.values().all(|(target, speed)| speed == target)
+            // still ramping up over --soft-start
+            && (self.soft_start_since.is_none() || !self.vibrating())
+    }
+}
+
+fn towards(speed: Speed, target: Speed, step: Option<Speed>) -> Speed {
+    match step {
+        Some(step) if (target - speed).abs() > step => speed + step.copysign(target - speed),
+        _ => target,
+    }
+}
+
+/// Sends the shaped vibration speed, reaching it at the slew_rate from --config,
+/// or over --soft-start after the device (re)appeared
+pub async fn vibrate(device: &Device, states: &DeviceStates, speed: Speed) -> Result<(), ()> {
+    let device_name = normalize_device_name(device.name());
+    // e.g. a pattern tick racing the emergency stop
+    if states.estop() {
+        return Ok(());
+    }
+    states.with(&device_name, |state| {
+        state.target = speed;
+        // the whole device includes the motors set apart
+        state.motors.clear();
+    });
+    send(device, states, &device_name, None).await
+}
+
+/// Sends the shaped vibration speeds of the motors by their indexes, as `vibrate` does
+pub async fn vibrate_motors(device: &Device, states: &DeviceStates, speeds: Vec<(FeatureIndex, Speed)>) -> Result<(), ()> {
+    let device_name = normalize_device_name(device.name());
+    if states.estop() {
+        return Ok(());
+    }
+    let indexes = speeds.iter().map(|(index, _)| *index).collect();
+    states.with(&device_name, |state| {
+        // the rest of the motors stay at the current speed of the whole device
+        state.target = state.speed;
+        let current = state.speed;
+        for (index, speed) in speeds {
+            state.motors.entry(index).or_insert((current, current)).0 = speed;
+        }
+    });
+    send(device, states, &device_name, Some(indexes)).await
+}
+
+/// Sends the first step towards the requested speeds of the whole device, or of the motors by their indexes;
+/// the rest is sent by the background sender
+async fn send(device: &Device, states: &DeviceStates, device_name: &str, indexes: Option<Vec<FeatureIndex>>) -> Result<(), ()> {
+    let step = states.slew_step(device_name);
+    let command = states.with(device_name, |state| {
+        if state.sender.is_some() {
+            // already on the way, towards the new speeds now
+            return None;
+        }
+        let envelope = states.envelope(state);
+        state.advance(step, envelope);
+        if !state.reached() {
+            state.sender = Some(tokio::task::spawn(sender(device.clone(), states.clone(), String::from(device_name))));
+        }
+        Some(match indexes {
+            None => (Some(state.speed), Vec::new()),
+            Some(indexes) => (None, indexes.into_iter()
+                .filter_map(|index| state.motors.get(&index).map(|(_, speed)| (index, *speed)))
+                .collect()),
+        })
+    });
+    match command {
+        Some((speed, motors)) => {
+            debug!("[{}] adjusting vibration", device_name);
+            send_speeds(device, states, device_name, speed, motors).await
+        }
+        None => Ok(()),
+    }
+}
+
+/// Sends the steps towards the requested speeds until they are reached
+async fn sender(device: Device, states: DeviceStates, device_name: String) {
+    let mut interval = time::interval(Duration::from_secs_f64(1.0 / states.tick_rate));
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let step = states.slew_step(&device_name);
+        let estop = states.estop();
+        let (reached, (speed, motors)) = states.with(&device_name, |state| {
+            let advanced = if estop {
+                (None, Vec::new())
+            } else {
+                let envelope = states.envelope(state);
+                state.advance(step, envelope)
+            };
+            let reached = estop || state.reached();
+            if reached {
+                state.sender = None;
+            }
+            (reached, advanced)
+        });
+        let _ = send_speeds(&device, &states, &device_name, speed, motors).await;
+        if reached {
+            return;
+        }
+    }
+}
+
+async fn send_speeds(
+    device: &Device, states: &DeviceStates, device_name: &str, speed: Option<Speed>, motors: Vec<(FeatureIndex, Speed)>,
+) -> Result<(), ()> {
+    let command = match speed {
+        Some(speed) => ScalarValueCommand::ScalarValue(states.shape(device_name, speed)),
+        None if !motors.is_empty() => ScalarValueCommand::ScalarValueMap(
+            motors.into_iter().map(|(index, speed)| (index, states.shape(device_name, speed))).collect()
+        ),
+        None => return Ok(()),
+    };
+    device.vibrate(&command).await.map_err(|e|
+        error!("{:?}", e)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc_send::OscSender;
+    use crate::simulate::SimulatedDevice;
+
+    #[test]
+    fn towards_the_target_by_step() {
+        assert_eq!(towards(0.0, 1.0, None), 1.0);
+        assert_eq!(towards(0.0, 1.0, Some(0.25)), 0.25);
+        assert_eq!(towards(1.0, 0.0, Some(0.25)), 0.75);
+        // the rest of the way
+        assert_eq!(towards(0.9, 1.0, Some(0.25)), 1.0);
+        assert_eq!(towards(0.5, 0.5, Some(0.25)), 0.5);
+    }
+
+    fn soft_started() -> DeviceStates {
+        DeviceStates::new(Shaping::default(), None, Some(Duration::from_secs(3600)), 10.0)
+    }
+
+    /// Sent vibration speed after the first step towards the requested one
+    fn vibrate(states: &DeviceStates, name: &str, speed: Speed) -> Speed {
+        states.with(name, |state| {
+            state.target = speed;
+            let envelope = states.envelope(state);
+            state.advance(None, envelope);
+            state.speed
+        })
+    }
+
+    #[test]
+    fn expired_once_per_command() {
+        let states = DeviceStates::new(Shaping::default(), None, None, 10.0);
+        // never commanded
+        states.added("Edge");
+        assert!(states.expired(Duration::ZERO).is_empty());
+        states.commanded("Hush");
+        assert!(states.expired(Duration::from_secs(3600)).is_empty());
+        assert_eq!(states.expired(Duration::ZERO), vec![String::from("Hush")]);
+        // stopped already, until the next command
+        assert!(states.expired(Duration::ZERO).is_empty());
+        states.commanded("Hush");
+        assert_eq!(states.expired(Duration::ZERO), vec![String::from("Hush")]);
+    }
+
+    fn simulated() -> Device {
+        let simulated: SimulatedDevice = "Test:1:0".parse().expect("unexpected");
+        Device::new(Arc::new(simulated.backend(0, Arc::new(OscSender::new(None, "", None)))), None)
+    }
+
+    #[tokio::test]
+    async fn estop_holds_until_reset() {
+        let (states, device) = (DeviceStates::new(Shaping::default(), None, None, 10.0), simulated());
+        states.set_estop(true);
+        super::vibrate(&device, &states, 1.0).await.expect("unexpected");
+        vibrate_motors(&device, &states, vec![(0, 1.0)]).await.expect("unexpected");
+        assert_eq!(states.speed("Test"), 0.0);
+        states.set_estop(false);
+        super::vibrate(&device, &states, 1.0).await.expect("unexpected");
+        assert_eq!(states.speed("Test"), 1.0);
+    }
+
+    #[tokio::test]
+    async fn estop_halts_the_ramp() {
+        let (states, device) = (soft_started(), simulated());
+        states.added("Test");
+        super::vibrate(&device, &states, 1.0).await.expect("unexpected");
+        let ramping = states.speed("Test");
+        assert!(states.with("Test", |state| state.sender.is_some()));
+        states.set_estop(true);
+        time::sleep(Duration::from_millis(300)).await;
+        assert!(states.with("Test", |state| state.sender.is_none()));
+        assert_eq!(states.speed("Test"), ramping);
+    }
+
+    #[test]
+    fn soft_start_survives_the_stop() {
+        let states = soft_started();
+        states.added("Hush");
+        states.reset("Hush");
+        assert!(vibrate(&states, "Hush", 1.0) < 0.01);
+        // stopped while ramping up, starts over
+        states.reset("Hush");
+        assert!(vibrate(&states, "Hush", 1.0) < 0.01);
+    }
+
+    #[test]
+    fn soft_start_waits_for_a_vibration() {
+        let states = soft_started();
+        states.added("Hush");
+        states.commanded("Hush");
+        assert_eq!(vibrate(&states, "Hush", 0.0), 0.0);
+        assert!(states.with("Hush", |state| state.soft_start && state.soft_start_since.is_none()));
+        assert!(vibrate(&states, "Hush", 1.0) < 0.01);
+        // others are not ramped
+        assert_eq!(vibrate(&states, "Edge", 1.0), 1.0);
+    }
+}
@@ -0,0 +1,89 @@
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{debug, info, warn};
+use crate::{DEVICES_ALL, OscHandler};
+use crate::osc_send::OscSender;
+use crate::websocket::{self, JsonMessage};
+
+/// `{"device": "all", "cmd": "vibrate", "speed": 0.4}` is /devices/all/vibrate/speed 0.4
+/// (`vibrate` and `rotate` are short for `vibrate/speed` and `rotate/speed`), the other commands are
+/// as in the OSC addresses, e.g. `{"device": "HushA", "cmd": "vibrate/pulse", "args": [1.0, 500]}`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonCommand {
+    #[serde(default = "default_device")]
+    device: String,
+    cmd: String,
+    speed: Option<f64>,
+    #[serde(default)]
+    args: Vec<Value>,
+}
+
+fn default_device() -> String {
+    String::from(DEVICES_ALL)
+}
+
+/// A command, or the OSC message as with --websocket-listen
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonLine {
+    Command(JsonCommand),
+    Message(JsonMessage),
+}
+
+/// Accepts the JSON lines from stdin as OSC messages until it's closed
+pub fn stdin_listen(osc_handler: OscHandler) {
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("[stdin] {:?}", e);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        debug!("[stdin] {}", line);
+        match serde_json::from_str::<JsonLine>(&line).map_err(|e| e.to_string()).and_then(|line| osc_message(line, &osc_handler.namespace)) {
+            Ok(message) => osc_handler.handle_packet(osc::Packet::Message(message)),
+            Err(e) => warn!("[stdin] {}", e),
+        }
+    }
+    info!("[stdin] closed");
+}
+
+/// Writes the messages sent via --osc-send to stdout as JSON lines
+pub async fn stdout_send(osc_send: Arc<OscSender>) {
+    let mut events = osc_send.subscribe();
+    while let Ok((address, args)) = events.recv().await {
+        let message = JsonMessage { address, args: args.iter().map(websocket::json_arg).collect() };
+        let mut stdout = io::stdout();
+        if writeln!(stdout, "{}", serde_json::to_string(&message).expect("unexpected")).and_then(|_| stdout.flush()).is_err() {
+            break;
+        }
+    }
+}
+
+fn osc_message(line: JsonLine, namespace: &str) -> Result<osc::Message, String> {
+    let command = match line {
+        JsonLine::Message(message) => return websocket::osc_message(message),
+        JsonLine::Command(command) => command,
+    };
+    let cmd = match command.cmd.as_str() {
+        "vibrate" | "rotate" => format!("{}/speed", command.cmd),
+        _ => command.cmd,
+    };
+    let mut message = websocket::osc_message(JsonMessage {
+        address: format!("{}/devices/{}/{}", namespace, command.device, cmd),
+        args: command.args,
+    })?;
+    if let (Some(speed), Some(args)) = (command.speed, message.args.as_mut()) {
+        args.insert(0, OscType::Double(speed));
+    }
+    Ok(message)
+}
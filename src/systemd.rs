@@ -0,0 +1,28 @@
+// https://www.freedesktop.org/software/systemd/man/sd_notify.html
+
+use std::time::Duration;
+use sd_notify::NotifyState;
+use tracing::{debug, warn};
+
+/// Tells systemd that the bridge is serving (for `Type=notify` units), and feeds the watchdog (`WatchdogSec=`)
+/// while the runtime is responsive; does nothing outside of systemd
+pub fn ready() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        warn!("[systemd] {:?}", e);
+    }
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+    let period = Duration::from_micros(usec) / 2;
+    debug!("[systemd] watchdog each {:.1}s", period.as_secs_f64());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                warn!("[systemd] {:?}", e);
+            }
+        }
+    });
+}
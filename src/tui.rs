@@ -0,0 +1,103 @@
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::Terminal;
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table};
+use crate::{Device, DEVICES_LAST};
+use crate::intiface::ServerStatus;
+use crate::metrics::METRICS;
+use crate::state::DeviceStates;
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Live view of the servers, devices and OSC traffic, until `q` is pressed
+pub fn tui(devices: evmap::ReadHandle<&'static str, Device>, states: DeviceStates, server_status: Arc<ServerStatus>) -> io::Result<()> {
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let result = run(&mut terminal, &devices, &states, &server_status);
+    terminal::disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
+
+fn run(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    devices: &evmap::ReadHandle<&'static str, Device>,
+    states: &DeviceStates,
+    server_status: &ServerStatus,
+) -> io::Result<()> {
+    // messages per second over the last second
+    let (mut rate, mut counted, mut counted_at) = (0.0, METRICS.received_total(), Instant::now());
+    loop {
+        if counted_at.elapsed() >= Duration::from_secs(1) {
+            let received = METRICS.received_total();
+            rate = (received - counted) as f64 / counted_at.elapsed().as_secs_f64();
+            counted = received;
+            counted_at = Instant::now();
+        }
+        let servers: Vec<Row> = server_status.states().into_iter()
+            .map(|(name, address, state)| Row::new(vec![name, address, String::from(state)]))
+            .collect();
+        let mut names: Vec<&'static str> = devices.read()
+            .map(|map| map.iter().map(|(name, _)| *name).collect())
+            .unwrap_or_default();
+        // <server>/<name> are the same devices
+        names.retain(|name| *name != DEVICES_LAST && !name.contains('/'));
+        names.sort_unstable();
+        let device_rows: Vec<Row> = names.iter()
+            .map(|name| {
+                let (commands, errors) = METRICS.commands(name);
+                Row::new(vec![
+                    String::from(*name),
+                    format!("{:.2}", states.speed(name)),
+                    commands.to_string(),
+                    errors.to_string(),
+                ])
+            })
+            .collect();
+        let traffic = format!(
+            "{:.1} messages/s, last from {}    (q to quit)",
+            rate, METRICS.last_source().unwrap_or_else(|| String::from("-")),
+        );
+        terminal.draw(|frame| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(servers.len() as u16 + 3), Constraint::Min(3), Constraint::Length(3)].as_ref())
+                .split(frame.size());
+            frame.render_widget(
+                Table::new(servers)
+                    .header(Row::new(vec!["Server", "Address", "State"]))
+                    .block(Block::default().title("Intiface").borders(Borders::ALL))
+                    .widths(&[Constraint::Percentage(20), Constraint::Percentage(60), Constraint::Percentage(20)]),
+                chunks[0],
+            );
+            frame.render_widget(
+                Table::new(device_rows)
+                    .header(Row::new(vec!["Device", "Speed", "Commands", "Errors"]))
+                    .block(Block::default().title("Devices").borders(Borders::ALL))
+                    .widths(&[Constraint::Percentage(55), Constraint::Percentage(15), Constraint::Percentage(15), Constraint::Percentage(15)]),
+                chunks[1],
+            );
+            frame.render_widget(
+                Paragraph::new(traffic.as_str()).block(Block::default().title("OSC").borders(Borders::ALL)),
+                chunks[2],
+            );
+        })?;
+        if event::poll(REFRESH_INTERVAL)? {
+            if let Event::Key(key) = event::read()? {
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+use crate::config::{Combine, RouteConfig};
+
+pub const AVATAR: &str = "/avatar/";
+const PARAMETERS: &str = "/avatar/parameters/";
+/// Where VRChat sends the avatar parameters to
+pub const OSC_LISTEN: &str = "udp://0.0.0.0:9001";
+
+/// Routes of the `[avatar_parameters]` to the vibration speed of the devices
+pub fn routes(parameters: &std::collections::HashMap<String, String>) -> Vec<RouteConfig> {
+    parameters.iter()
+        .map(|(name, device)| RouteConfig {
+            address: format!("{}{}", PARAMETERS, name),
+            device: device.clone(),
+            command: String::from("vibrate/speed"),
+            input: None,
+            deadzone: 0.0,
+            transform: None,
+            threshold: 0.0,
+            scale: (0.0, 1.0),
+            smoothing: 0.0,
+            min_interval_ms: 0,
+            min_change: 0.0,
+            when: None,
+            combine: Combine::Latest,
+        })
+        .collect()
+}
+
+/// Int parameter (0..255) as float (0..1), routed so unless the route declares its input range
+pub fn int_parameter(addr: &str, x: f64) -> Option<f64> {
+    if addr.starts_with(PARAMETERS) { Some(x / 255.0) } else { None }
+}
+
+/// Int parameters as floats, as forwarded; float and bool ones as is
+pub fn normalize(mut message: osc::Message) -> osc::Message {
+    if let Some(ref mut args) = message.args {
+        for arg in args.iter_mut() {
+            if let OscType::Int(x) = *arg {
+                if let Some(x) = int_parameter(&message.addr, f64::from(x)) {
+                    *arg = OscType::Float(x as f32);
+                }
+            }
+        }
+    }
+    message
+}
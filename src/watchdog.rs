@@ -0,0 +1,33 @@
+use std::time::Duration;
+use tracing::{error, warn};
+use crate::{Device, normalize_device_name, unique_devices};
+use crate::state::DeviceStates;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Stops each device not commanded for the window, e.g. when the controller crashed,
+/// instead of leaving it at the last speed; a device playing a pattern also needs a command within the window
+pub async fn watchdog(window: Duration, devices: evmap::ReadHandle<&'static str, Device>, states: DeviceStates) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let expired = states.expired(window);
+        if expired.is_empty() {
+            continue;
+        }
+        for device in unique_devices(&devices) {
+            let device_name = normalize_device_name(device.name());
+            if !expired.contains(&device_name) {
+                continue;
+            }
+            warn!("[{}] no command for {}s, stopping (--watchdog)", device_name, window.as_secs());
+            states.cancel_task(&device_name);
+            states.reset(&device_name);
+            tokio::spawn(async move {
+                device.stop().await.map_err(|e|
+                    error!("{:?}", e)
+                )
+            });
+        }
+    }
+}
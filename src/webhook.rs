@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+use tracing::{debug, info, warn};
+use crate::OscHandler;
+use crate::config::WebhookConfig;
+
+/// Sends the commands of `[webhooks.<event>]` for every POST request, the event being `"event"` of the JSON body
+/// (as sent by IFTTT and the stream alert services) or else the last segment of the path
+pub fn webhook_listen(host_port: &str, webhooks: HashMap<String, WebhookConfig>, osc_handler: OscHandler) {
+    let server = tiny_http::Server::http(host_port).expect("Invalid --webhook-listen: couldn't bind socket");
+    for mut request in server.incoming_requests() {
+        debug!("[Webhook] {} {}", request.method(), request.url());
+        if *request.method() != tiny_http::Method::Post {
+            if let Err(e) = request.respond(tiny_http::Response::empty(405)) {
+                warn!("[Webhook] {:?}", e);
+            }
+            continue;
+        }
+        let mut body = String::new();
+        if let Err(e) = request.as_reader().read_to_string(&mut body) {
+            warn!("[Webhook] {:?}", e);
+        }
+        let event = serde_json::from_str::<serde_json::Value>(&body).ok()
+            .and_then(|json| json.get("event").and_then(|event| event.as_str()).map(String::from))
+            .unwrap_or_else(|| {
+                let path = request.url().split('?').next().unwrap_or_default();
+                String::from(path.rsplit('/').find(|name| !name.is_empty()).unwrap_or_default())
+            });
+        let status = match webhooks.get(&event) {
+            Some(webhook) => {
+                info!("[Webhook] {}", event);
+                osc_handler.handle_packet(osc::Packet::Message(message(webhook, &osc_handler.namespace)));
+                200
+            }
+            None => {
+                warn!("[Webhook] unknown event {:?}", event);
+                404
+            }
+        };
+        if let Err(e) = request.respond(tiny_http::Response::empty(status)) {
+            warn!("[Webhook] {:?}", e);
+        }
+    }
+}
+
+fn message(webhook: &WebhookConfig, namespace: &str) -> osc::Message {
+    let (command, args) = match webhook.pattern {
+        Some(ref pattern) => ("pattern", vec![OscType::String(pattern.clone())]),
+        None => (
+            webhook.command.as_deref().unwrap_or_default(),
+            webhook.args.iter().filter_map(arg).collect(),
+        ),
+    };
+    osc::Message {
+        addr: format!("{}/devices/{}/{}", namespace, webhook.device, command),
+        args: Some(args),
+    }
+}
+
+fn arg(value: &toml::Value) -> Option<OscType> {
+    match value {
+        toml::Value::Integer(x) => Some(OscType::Long(*x)),
+        toml::Value::Float(x) => Some(OscType::Double(*x)),
+        toml::Value::Boolean(x) => Some(OscType::Bool(*x)),
+        toml::Value::String(x) => Some(OscType::String(x.clone())),
+        _ => None,
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+use futures_util::{SinkExt, StreamExt};
+use nannou_osc as osc;
+use nannou_osc::rosc::OscType;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+use crate::OscHandler;
+use crate::metrics::METRICS;
+use crate::osc_send::OscSender;
+
+/// `{"address": "/devices/all/vibrate/speed", "args": [0.5]}`, as the OSC message
+#[derive(Deserialize, Serialize)]
+pub struct JsonMessage {
+    pub address: String,
+    #[serde(default)]
+    pub args: Vec<Value>,
+}
+
+/// Accepts the JSON messages as OSC ones, and pushes the messages sent via --osc-send to every client
+pub async fn websocket_listen(host_port: String, osc_handler: OscHandler, osc_send: Arc<OscSender>) {
+    let listener = TcpListener::bind(&host_port).await.expect("Invalid --websocket-listen: couldn't bind socket");
+    METRICS.listener("websocket", true);
+    loop {
+        match listener.accept().await {
+            Ok((stream, source)) => {
+                tokio::spawn(handle_connection(stream, source.to_string(), osc_handler.clone(), osc_send.clone()));
+            }
+            Err(e) => warn!("[WebSocket] {:?}", e),
+        }
+    }
+}
+
+async fn handle_connection(stream: TcpStream, source: String, osc_handler: OscHandler, osc_send: Arc<OscSender>) {
+    let websocket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(websocket) => websocket,
+        Err(e) => {
+            warn!("[WebSocket {}] {:?}", source, e);
+            return;
+        }
+    };
+    info!("[WebSocket {}] connected", source);
+    let (mut sink, mut stream) = websocket.split();
+    let mut events = osc_send.subscribe();
+    let push = tokio::spawn(async move {
+        while let Ok((address, args)) = events.recv().await {
+            let message = JsonMessage { address, args: args.iter().map(json_arg).collect() };
+            let text = serde_json::to_string(&message).expect("unexpected");
+            if sink.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+    while let Some(Ok(message)) = stream.next().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        debug!("[WebSocket {}] {}", source, text);
+        match serde_json::from_str::<JsonMessage>(&text).map_err(|e| e.to_string()).and_then(osc_message) {
+            Ok(message) => osc_handler.handle_packet(osc::Packet::Message(message)),
+            Err(e) => warn!("[WebSocket {}] {}", source, e),
+        }
+    }
+    push.abort();
+    info!("[WebSocket {}] disconnected", source);
+}
+
+/// Numbers are floats
+pub fn osc_message(message: JsonMessage) -> Result<osc::Message, String> {
+    let args = message.args.into_iter()
+        .map(|arg| match arg {
+            Value::Number(x) => x.as_f64().map(OscType::Double).ok_or_else(|| format!("invalid argument value: {}", x)),
+            Value::Bool(x) => Ok(OscType::Bool(x)),
+            Value::String(x) => Ok(OscType::String(x)),
+            x => Err(format!("invalid argument value: {}", x)),
+        })
+        .collect::<Result<Vec<OscType>, String>>()?;
+    Ok(osc::Message { addr: message.address, args: Some(args) })
+}
+
+pub fn json_arg(arg: &OscType) -> Value {
+    match arg {
+        OscType::Int(x) => Value::from(*x),
+        OscType::Long(x) => Value::from(*x),
+        OscType::Float(x) => Value::from(*x),
+        OscType::Double(x) => Value::from(*x),
+        OscType::Bool(x) => Value::from(*x),
+        OscType::String(x) => Value::from(x.clone()),
+        x => Value::from(format!("{:?}", x)),
+    }
+}